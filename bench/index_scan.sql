@@ -0,0 +1,4 @@
+-- pgbench script which reads the 50 rows of a random category through the index
+SET enable_seqscan = off;
+\set category random(0, 999)
+SELECT * FROM bench_items WHERE category = :category;
@@ -0,0 +1,11 @@
+-- Table for the index scan benchmark, see DEVELOP.md. Rows are inserted in several transactions to stay within
+-- FoundationDB's transaction limits.
+DROP TABLE IF EXISTS bench_items;
+CREATE TABLE bench_items (id INTEGER, category INTEGER, name TEXT) USING pgfdb_table;
+CREATE INDEX bench_items_category_idx ON bench_items USING pgfdb(category);
+
+INSERT INTO bench_items SELECT i, i % 1000, 'item ' || i FROM generate_series(1, 10000) AS i;
+INSERT INTO bench_items SELECT i, i % 1000, 'item ' || i FROM generate_series(10001, 20000) AS i;
+INSERT INTO bench_items SELECT i, i % 1000, 'item ' || i FROM generate_series(20001, 30000) AS i;
+INSERT INTO bench_items SELECT i, i % 1000, 'item ' || i FROM generate_series(30001, 40000) AS i;
+INSERT INTO bench_items SELECT i, i % 1000, 'item ' || i FROM generate_series(40001, 50000) AS i;
@@ -1,16 +1,56 @@
+use std::ffi::{c_char, c_void};
 use std::slice::from_raw_parts_mut;
 
-use crate::{errors::FdbErrorExt, iam::utils::encode_datum_for_index};
-use foundationdb::RangeOption;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+use crate::{
+    errors::FdbErrorExt,
+    iam::{
+        state::{self, IndexState},
+        utils::{ColumnOrder, encode_datum_for_index, is_supported_type, type_name},
+    },
+    transaction::{
+        chunk_ranges, plan_chunks, run_in_batches, run_in_separate_transaction, unpack_cuts,
+    },
+};
+use foundationdb::{
+    KeySelector, RangeOption, Transaction,
+    future::FdbValues,
+    options::StreamingMode,
+    tuple::{Element, unpack},
+};
 use futures::StreamExt;
 use pg_sys::{Datum, IndexBuildResult, IndexInfo, IndexUniqueCheck, ItemPointer, Relation};
 use pgrx::{
-    pg_sys::{FormData_pg_attribute, Oid, TupleTableSlot},
+    pg_sys::{FormData_pg_attribute, Oid, RelFileNumber, TupleTableSlot, panic::ErrorReportable},
     prelude::*,
 };
 use pollster::FutureExt;
 
-// Index build function - Called when CREATE INDEX is executed
+// Number of table rows to index per transaction in online index builds
+const BUILD_BATCH_SIZE: usize = 1000;
+
+// Largest table size in bytes which is rebuilt within the session transaction, which leaves plenty of room for the
+// index entries within the 10 MB write limit of a transaction
+const SESSION_REBUILD_BYTES: i64 = 1024 * 1024;
+
+// Build phases reported to `pg_stat_progress_create_index`, which follow the generic initialization phase
+const PHASE_SCANNING_TABLE: i64 = 2;
+const PHASE_WRITING_KEYS: i64 = 3;
+// Reported while `CREATE INDEX CONCURRENTLY` validates the index, see `validate_online`
+const PHASE_VALIDATING: i64 = 4;
+
+// Index build function - Called when CREATE INDEX or REINDEX is executed. REINDEX gives the index new storage, which
+// only replaces the old storage once the transaction commits (see `subspace::index`). If a REINDEX fails, the index
+// keeps its old entries, and any entries already built into the new storage are cleared on abort.
+//
+// Large tables can't be indexed within the 5 second limit of a single transaction, so we build indexes online in
+// batches which each run in a transaction of their own (see `build_online`). Rows written earlier in the current
+// transaction are only visible to the session transaction though, so if the table has been written to, we build
+// the index within the session transaction instead. Indexes of small tables are also rebuilt within the session
+// transaction, so that the new entries are committed together with the new storage. If Postgres has planned
+// parallel workers for the build, which depends on `max_parallel_maintenance_workers`, the batches are split between
+// them (see `build_parallel`).
 pub unsafe extern "C-unwind" fn ambuild(
     heap_relation: Relation,
     index_relation: Relation,
@@ -19,14 +59,90 @@ pub unsafe extern "C-unwind" fn ambuild(
     unsafe {
         log!("IAM: Build index");
 
-        let mut num_rows = 0;
-        let index_oid = (*index_relation).rd_id;
+        check_supported_types(index_relation);
+
+        // The storage replaced by a rebuild has to be cleared once the transaction commits
+        let rebuild = (*index_relation).rd_newRelfilelocatorSubid != 0;
+        if rebuild {
+            crate::transaction::record_rebuild();
+        }
+
+        let table_oid = (*heap_relation).rd_id;
+        let (num_rows, num_index_rows) = if crate::transaction::has_written(table_oid)
+            || (rebuild && fits_in_session(table_oid))
+        {
+            build_in_session(heap_relation, index_relation, index_info)
+        } else if (*index_info).ii_ParallelWorkers > 0 {
+            build_parallel(heap_relation, index_relation, index_info)
+        } else {
+            build_online(heap_relation, index_relation, index_info)
+        };
+
+        let mut build_result = PgBox::<IndexBuildResult>::alloc();
+        build_result.heap_tuples = num_rows;
+        build_result.index_tuples = num_index_rows;
+        build_result.into_pg()
+    }
+}
+
+// Checks if a table is small enough to be rebuilt within the session transaction, going by the size estimate of FDB
+fn fits_in_session(table_oid: Oid) -> bool {
+    let txn = crate::transaction::get_transaction();
+    let (start, end) = crate::subspace::table(table_oid).range();
+    let estimated_bytes = txn
+        .get_estimated_range_size_bytes(&start, &end)
+        .block_on()
+        .unwrap_or_pg_error();
+    estimated_bytes <= SESSION_REBUILD_BYTES
+}
+
+// Our operator classes for arrays and records accept any element type, so an index could be created on a column we
+// can't encode, like `inet[]`. We check this up front rather than failing on the first row.
+unsafe fn check_supported_types(index_relation: Relation) {
+    unsafe {
+        let tuple_desc = (*index_relation).rd_att;
+        for attr in (*tuple_desc).attrs.as_slice((*tuple_desc).natts as usize) {
+            if !is_supported_type(attr.atttypid) {
+                ereport!(
+                    ERROR,
+                    PgSqlErrorCode::ERRCODE_FEATURE_NOT_SUPPORTED,
+                    &format!(
+                        "pgfdb indexes don't support data type {}",
+                        type_name(attr.atttypid)
+                    )
+                );
+            }
+        }
+    }
+}
+
+// Builds an index within the session transaction, returning the number of table rows and index entries
+unsafe fn build_in_session(
+    heap_relation: Relation,
+    index_relation: Relation,
+    index_info: *mut IndexInfo,
+) -> (f64, f64) {
+    unsafe {
+        let mut num_rows = 0.0;
+        let mut num_index_rows = 0.0;
+        let relfilenumber = (*index_relation).rd_locator.relNumber;
         let table_oid = (*heap_relation).rd_id;
         let table_subspace = crate::subspace::table(table_oid);
 
         let txn = crate::transaction::get_transaction();
         let range_option = RangeOption::from(table_subspace.range());
 
+        // Rebuilt indexes usually get new and empty storage, but truncating a table created in the current
+        // transaction rebuilds its indexes in place. The old entries are cleared in the same transaction as the new
+        // ones are written, so the contents of the index are replaced atomically.
+        let index_subspace = crate::subspace::index(relfilenumber);
+        txn.clear_subspace_range(&index_subspace);
+
+        // Keys are written to the session transaction as we scan the table, so there's only a single phase
+        let (total_rows, _) =
+            crate::tam::estimate_table_size(txn, heap_relation).unwrap_or_pg_error();
+        report_progress_start(PHASE_SCANNING_TABLE, total_rows);
+
         // Create a slot for the heap tuple
         let heap_tuple_desc = (*heap_relation).rd_att;
         let heap_slot =
@@ -41,24 +157,403 @@ pub unsafe extern "C-unwind" fn ambuild(
             // Load the tuple into the heap slot
             tuple.load_into_tts(heap_slot.as_mut().unwrap());
 
-            // Build and set the index key
-            let key =
-                build_key_from_table_tuple(index_oid, id, index_relation, heap_slot, index_info);
-            txn.set(&key, &[]);
+            // Build and set the index key, unless the row is excluded by the predicate of a partial index
+            if let Some(key) = build_key_from_table_tuple(id, index_relation, heap_slot, index_info)
+            {
+                txn.set(&key, &[]);
+                num_index_rows += 1.0;
+            }
 
-            num_rows += 1;
+            num_rows += 1.0;
+            report_progress(pg_sys::PROGRESS_CREATEIDX_TUPLES_DONE, num_rows as i64);
         }
 
         // Free the heap slot
         pgrx::pg_sys::ExecDropSingleTupleTableSlot(heap_slot);
 
-        let mut build_result = PgBox::<IndexBuildResult>::alloc();
-        build_result.heap_tuples = num_rows.into();
-        build_result.index_tuples = num_rows.into();
-        build_result.into_pg()
+        state::set(txn, relfilenumber, IndexState::Readable);
+
+        (num_rows, num_index_rows)
+    }
+}
+
+// Builds an index in batches across many transactions, returning the number of table rows and index entries.
+//
+// The index is first marked as write-only, which makes the planner ignore it while writes to the table still keep
+// it up to date. Each batch indexes the rows after the cursor stored in the index state and moves the cursor
+// forward in the same transaction, so a batch which has to be retried picks up where the last committed one left
+// off. The cursor only covers the batches of a single build, and a build always starts over from the beginning of
+// the table. FDB detects conflicts between a batch and concurrent writes to the rows it has read, which means no rows are
+// missed or indexed with stale values. Once all rows have been indexed, the index is marked as readable.
+pub unsafe fn build_online(
+    heap_relation: Relation,
+    index_relation: Relation,
+    index_info: *mut IndexInfo,
+) -> (f64, f64) {
+    unsafe {
+        build_in_batches(
+            heap_relation,
+            index_relation,
+            index_info,
+            PHASE_SCANNING_TABLE,
+        )
+    }
+}
+
+// Validates an index built by `CREATE INDEX CONCURRENTLY`, returning the number of table rows. Writers only start
+// maintaining the index after its initial build, so rows written during the build can be missing from it. Running
+// the online build again indexes those rows, and writers maintain the index from here on. The whole build is
+// reported as the validating phase.
+pub unsafe fn validate_online(
+    heap_relation: Relation,
+    index_relation: Relation,
+    index_info: *mut IndexInfo,
+) -> f64 {
+    unsafe {
+        let (num_rows, _) =
+            build_in_batches(heap_relation, index_relation, index_info, PHASE_VALIDATING);
+        num_rows
     }
 }
 
+// The batches of `build_online`, reporting the given phase. Each batch writes the keys for the rows it scans in the
+// same transaction, so like `build_in_session` there's only a single phase.
+unsafe fn build_in_batches(
+    heap_relation: Relation,
+    index_relation: Relation,
+    index_info: *mut IndexInfo,
+    phase: i64,
+) -> (f64, f64) {
+    unsafe {
+        let mut num_rows = 0.0;
+        let mut num_index_rows = 0.0;
+        let relfilenumber = (*index_relation).rd_locator.relNumber;
+        let table_oid = (*heap_relation).rd_id;
+        let table_subspace = crate::subspace::table(table_oid);
+
+        // The storage of the index is marked as write-only, so the planner won't use it until it has been
+        // completely built. A REINDEX builds into new storage, so the old entries stay in place until it commits.
+        let index_subspace = crate::subspace::index(relfilenumber);
+        record_online_build(index_relation);
+        run_in_separate_transaction(|txn| {
+            txn.clear_subspace_range(&index_subspace);
+            state::set(txn, relfilenumber, IndexState::WriteOnly { cursor: None });
+            Ok(())
+        });
+        let (total_rows, _) =
+            run_in_separate_transaction(|txn| crate::tam::estimate_table_size(txn, heap_relation));
+        report_progress_start(phase, total_rows);
+
+        // Create a slot for the heap tuple
+        let heap_tuple_desc = (*heap_relation).rd_att;
+        let heap_slot =
+            pgrx::pg_sys::MakeSingleTupleTableSlot(heap_tuple_desc, &pgrx::pg_sys::TTSOpsVirtual);
+
+        loop {
+            let (batch_rows, batch_index_rows, done) = run_in_separate_transaction(|txn| {
+                let cursor = match state::get(txn, relfilenumber, false)? {
+                    Some(IndexState::WriteOnly { cursor }) => cursor,
+                    state => error!("unexpected state {:?} for index being built", state),
+                };
+
+                let (start, end) = table_subspace.range();
+                let begin = match cursor {
+                    Some(cursor) => KeySelector::first_greater_than(cursor),
+                    None => KeySelector::first_greater_or_equal(start),
+                };
+                let range_option = RangeOption {
+                    begin,
+                    end: KeySelector::first_greater_or_equal(end),
+                    limit: Some(BUILD_BATCH_SIZE),
+                    mode: StreamingMode::WantAll,
+                    ..RangeOption::from(table_subspace.range())
+                };
+                let values = txn.get_range(&range_option, 1, false).block_on()?;
+
+                let batch_index_rows =
+                    index_batch(txn, &values, index_relation, heap_slot, index_info);
+
+                if let Some(last) = values.last() {
+                    let cursor = Some(last.key().to_vec());
+                    state::set(txn, relfilenumber, IndexState::WriteOnly { cursor });
+                }
+
+                Ok((values.len() as f64, batch_index_rows as f64, !values.more()))
+            });
+
+            num_rows += batch_rows;
+            num_index_rows += batch_index_rows;
+            report_progress(pg_sys::PROGRESS_CREATEIDX_TUPLES_DONE, num_rows as i64);
+
+            if done {
+                break;
+            }
+        }
+
+        // Free the heap slot
+        pgrx::pg_sys::ExecDropSingleTupleTableSlot(heap_slot);
+
+        run_in_separate_transaction(|txn| {
+            state::set(txn, relfilenumber, IndexState::Readable);
+            Ok(())
+        });
+
+        (num_rows, num_index_rows)
+    }
+}
+
+// Online builds commit their work in separate transactions, which would be left behind if the transaction that
+// created the storage of the index aborts. For indexes created or rebuilt in the current transaction, we record the
+// build so that it is cleared on abort. Other indexes, like those built by CREATE INDEX CONCURRENTLY after the index
+// was created in an earlier transaction, outlive an abort of the build.
+unsafe fn record_online_build(index_relation: Relation) {
+    unsafe {
+        if (*index_relation).rd_createSubid != 0 || (*index_relation).rd_newRelfilelocatorSubid != 0
+        {
+            crate::transaction::record_online_build((*index_relation).rd_locator.relNumber);
+        }
+    }
+}
+
+// Sets the index keys for a batch of table rows, returning the number of keys set
+unsafe fn index_batch(
+    txn: &Transaction,
+    values: &FdbValues,
+    index_relation: Relation,
+    heap_slot: *mut TupleTableSlot,
+    index_info: *mut IndexInfo,
+) -> u64 {
+    unsafe {
+        let mut num_index_rows = 0;
+
+        for value in values.iter() {
+            let mut tuple = crate::coding::Tuple::deserialize(value.value());
+            let id = tuple.id;
+
+            // Load the tuple into the heap slot
+            tuple.load_into_tts(heap_slot.as_mut().unwrap());
+
+            // Build and set the index key, unless the row is excluded by the predicate of a partial index
+            if let Some(key) = build_key_from_table_tuple(id, index_relation, heap_slot, index_info)
+            {
+                txn.set(&key, &[]);
+                num_index_rows += 1;
+            }
+        }
+
+        num_index_rows
+    }
+}
+
+// Space for the cuts between the chunks of a parallel build in shared memory. If there are too many cuts to fit, we
+// use fewer and larger chunks.
+const PARALLEL_CUTS_SIZE: usize = 64 * 1024;
+
+// Key of the shared build state in the table of contents of the parallel context
+const PARALLEL_KEY_BUILD_STATE: u64 = 0xA000000000000001;
+
+// State of a parallel index build, shared between the leader and the workers. The table is split into chunks at
+// FDB split points, and the participants take turns claiming the next chunk to index.
+#[repr(C)]
+struct ParallelBuildState {
+    table_oid: Oid,
+    index_oid: Oid,
+    // Lock the workers take on the table, which depends on whether the index is built concurrently
+    table_lock_mode: pg_sys::LOCKMODE,
+    next_chunk: AtomicU32,
+    num_rows: AtomicU64,
+    num_index_rows: AtomicU64,
+    // The packed cuts between the chunks, see `plan_chunks`
+    cuts_len: u32,
+    cuts: [u8; PARALLEL_CUTS_SIZE],
+}
+
+// Builds an index like `build_online`, but with the table split into chunks which are indexed by parallel workers
+// as well as the leader. The chunks are indexed in batches which each run in a transaction of their own. Unlike
+// online builds, the progress isn't stored in the index state, as each participant keeps track of its own chunk.
+unsafe fn build_parallel(
+    heap_relation: Relation,
+    index_relation: Relation,
+    index_info: *mut IndexInfo,
+) -> (f64, f64) {
+    unsafe {
+        let index_oid = (*index_relation).rd_id;
+        let relfilenumber = (*index_relation).rd_locator.relNumber;
+        let table_oid = (*heap_relation).rd_id;
+        let table_subspace = crate::subspace::table(table_oid);
+
+        let index_subspace = crate::subspace::index(relfilenumber);
+        record_online_build(index_relation);
+        run_in_separate_transaction(|txn| {
+            txn.clear_subspace_range(&index_subspace);
+            state::set(txn, relfilenumber, IndexState::WriteOnly { cursor: None });
+            Ok(())
+        });
+        let (total_rows, _) =
+            run_in_separate_transaction(|txn| crate::tam::estimate_table_size(txn, heap_relation));
+        report_progress_start(PHASE_WRITING_KEYS, total_rows);
+
+        let ranges = [RangeOption::from(table_subspace.range())];
+        let (_, packed) =
+            run_in_separate_transaction(|txn| plan_chunks(txn, &ranges, PARALLEL_CUTS_SIZE));
+
+        // Set up the shared state and launch the workers, like the parallel builds of the built-in index types
+        pg_sys::EnterParallelMode();
+        let context = pg_sys::CreateParallelContext(
+            c"pgfdb".as_ptr(),
+            c"pgfdb_parallel_build_main".as_ptr(),
+            (*index_info).ii_ParallelWorkers,
+        );
+
+        // Like the `shm_toc_estimate_chunk` and `shm_toc_estimate_keys` macros
+        let state_size = size_of::<ParallelBuildState>();
+        (*context).estimator.space_for_chunks +=
+            state_size.next_multiple_of(pg_sys::ALIGNOF_BUFFER as usize);
+        (*context).estimator.number_of_keys += 1;
+        pg_sys::InitializeParallelDSM(context);
+
+        let state = pg_sys::shm_toc_allocate((*context).toc, state_size) as *mut ParallelBuildState;
+        std::ptr::write_bytes(state, 0, 1);
+        (*state).table_oid = table_oid;
+        (*state).index_oid = index_oid;
+        (*state).table_lock_mode = if (*index_info).ii_Concurrent {
+            pg_sys::ShareUpdateExclusiveLock as pg_sys::LOCKMODE
+        } else {
+            pg_sys::ShareLock as pg_sys::LOCKMODE
+        };
+        (*state).cuts[..packed.len()].copy_from_slice(&packed);
+        (*state).cuts_len = packed.len() as u32;
+        pg_sys::shm_toc_insert(
+            (*context).toc,
+            PARALLEL_KEY_BUILD_STATE,
+            state as *mut c_void,
+        );
+
+        pg_sys::LaunchParallelWorkers(context);
+        log!(
+            "IAM: Parallel build with {} workers",
+            (*context).nworkers_launched
+        );
+
+        // The leader indexes chunks as well, which also covers the case where no workers could be launched
+        build_chunks(state, heap_relation, index_relation, index_info);
+        pg_sys::WaitForParallelWorkersToFinish(context);
+
+        let num_rows = (*state).num_rows.load(Ordering::Acquire) as f64;
+        let num_index_rows = (*state).num_index_rows.load(Ordering::Acquire) as f64;
+        pg_sys::DestroyParallelContext(context);
+        pg_sys::ExitParallelMode();
+
+        run_in_separate_transaction(|txn| {
+            state::set(txn, relfilenumber, IndexState::Readable);
+            Ok(())
+        });
+
+        (num_rows, num_index_rows)
+    }
+}
+
+// Entry point of the parallel workers of an index build, which is looked up by name when they start
+#[pg_guard]
+#[unsafe(no_mangle)]
+pub extern "C-unwind" fn pgfdb_parallel_build_main(
+    _segment: *mut pg_sys::dsm_segment,
+    toc: *mut pg_sys::shm_toc,
+) {
+    unsafe {
+        let state =
+            pg_sys::shm_toc_lookup(toc, PARALLEL_KEY_BUILD_STATE, false) as *mut ParallelBuildState;
+
+        let heap_relation = pg_sys::table_open((*state).table_oid, (*state).table_lock_mode);
+        let index_relation = pg_sys::index_open(
+            (*state).index_oid,
+            pg_sys::RowExclusiveLock as pg_sys::LOCKMODE,
+        );
+        let index_info = pg_sys::BuildIndexInfo(index_relation);
+
+        build_chunks(state, heap_relation, index_relation, index_info);
+
+        pg_sys::index_close(index_relation, pg_sys::RowExclusiveLock as pg_sys::LOCKMODE);
+        pg_sys::table_close(heap_relation, (*state).table_lock_mode);
+    }
+}
+
+// Indexes chunks of the table until there are none left to claim
+unsafe fn build_chunks(
+    state: *mut ParallelBuildState,
+    heap_relation: Relation,
+    index_relation: Relation,
+    index_info: *mut IndexInfo,
+) {
+    unsafe {
+        let table_subspace = crate::subspace::table((*state).table_oid);
+        let ranges = [RangeOption::from(table_subspace.range())];
+        let cuts = unpack_cuts(&(*state).cuts[..(*state).cuts_len as usize]);
+
+        let heap_slot =
+            pg_sys::MakeSingleTupleTableSlot((*heap_relation).rd_att, &pg_sys::TTSOpsVirtual);
+
+        loop {
+            let chunk = (*state).next_chunk.fetch_add(1, Ordering::AcqRel) as usize;
+            if chunk + 1 >= cuts.len() {
+                break;
+            }
+
+            // The table is a single range, so each chunk is as well
+            let range = &chunk_ranges(&ranges, &cuts[chunk], &cuts[chunk + 1])[0];
+            let batches = run_in_batches(
+                (*state).table_oid,
+                (range.begin.key().to_vec(), range.end.key().to_vec()),
+                BUILD_BATCH_SIZE,
+                |txn, values| {
+                    let num_index_rows =
+                        index_batch(txn, values, index_relation, heap_slot, index_info);
+                    Ok((values.len() as u64, num_index_rows))
+                },
+            );
+
+            // Batches are only counted once committed, as they might have been retried
+            for (num_rows, num_index_rows) in batches {
+                (*state).num_rows.fetch_add(num_rows, Ordering::AcqRel);
+                (*state)
+                    .num_index_rows
+                    .fetch_add(num_index_rows, Ordering::AcqRel);
+            }
+
+            // Only the leader reports progress, which includes the rows indexed by the workers
+            if pg_sys::ParallelWorkerNumber < 0 {
+                let num_rows = (*state).num_rows.load(Ordering::Acquire);
+                report_progress(pg_sys::PROGRESS_CREATEIDX_TUPLES_DONE, num_rows as i64);
+            }
+        }
+
+        pg_sys::ExecDropSingleTupleTableSlot(heap_slot);
+    }
+}
+
+// Names of the build phases shown in `pg_stat_progress_create_index`. Validation of concurrently built indexes is
+// reported by Postgres with phases of its own.
+pub unsafe extern "C-unwind" fn ambuildphasename(phase: i64) -> *mut c_char {
+    let name = match phase {
+        PHASE_SCANNING_TABLE => c"scanning table",
+        PHASE_WRITING_KEYS => c"writing keys",
+        PHASE_VALIDATING => c"validating index",
+        _ => return std::ptr::null_mut(),
+    };
+    name.as_ptr() as *mut c_char
+}
+
+// Starts reporting the progress of a phase which scans the table. The total number of rows is an estimate.
+fn report_progress_start(phase: i64, total_rows: f64) {
+    report_progress(pg_sys::PROGRESS_CREATEIDX_SUBPHASE, phase);
+    report_progress(pg_sys::PROGRESS_CREATEIDX_TUPLES_TOTAL, total_rows as i64);
+    report_progress(pg_sys::PROGRESS_CREATEIDX_TUPLES_DONE, 0);
+}
+
+fn report_progress(param: u32, value: i64) {
+    unsafe { pg_sys::pgstat_progress_update_param(param as i32, value) };
+}
+
 pub unsafe extern "C-unwind" fn ambuildempty(_heap_relation: Relation) {
     log!("IAM: Build empty index");
 }
@@ -69,7 +564,7 @@ pub unsafe extern "C-unwind" fn aminsert(
     raw_values: *mut Datum,
     raw_isnull: *mut bool,
     tid: ItemPointer,
-    heap_relation: Relation,
+    _heap_relation: Relation,
     _check_unique: IndexUniqueCheck::Type,
     _index_unchanged: bool,
     _index_info: *mut IndexInfo,
@@ -83,59 +578,37 @@ pub unsafe extern "C-unwind" fn aminsert(
         // Get the number of attributes in the index
         let index_tuple_desc = (*index_relation).rd_att;
         let natts = (*index_tuple_desc).natts as usize;
-        let index_oid = (*index_relation).rd_id;
+        let relfilenumber = (*index_relation).rd_locator.relNumber;
         let attrs = (*index_tuple_desc).attrs.as_slice(natts);
+        let options = std::slice::from_raw_parts((*index_relation).rd_indoption, natts);
 
         let txn = crate::transaction::get_transaction();
 
-        // If this was an update, we need to clear any existing index key
-        // We directly use build_key_from_table_tuple like in tuple_delete
-        // TODO: It might be dangerous to rely on the tuple cache here
-        if let Some(mut tuple) = crate::tuple_cache::get_with_id(id) {
-            let heap_tuple_desc = (*heap_relation).rd_att;
-            let heap_slot = pgrx::pg_sys::MakeSingleTupleTableSlot(
-                heap_tuple_desc,
-                &pgrx::pg_sys::TTSOpsVirtual,
-            );
-
-            //  Load the tuple into the heap slot
-            tuple.load_into_tts(heap_slot.as_mut().unwrap());
-
-            // Build and clear the index key using existing slot
-            let index_info = pg_sys::BuildIndexInfo(index_relation);
-            let key = build_key_from_table_tuple(
-                index_oid,
-                tuple.id,
-                index_relation,
-                heap_slot,
-                index_info,
-            );
-            txn.clear(&key);
-
-            // Free the slot
-            pgrx::pg_sys::ExecDropSingleTupleTableSlot(heap_slot);
-        }
-
-        // Insert a new key for the indexed values which points back to the row being indexed
+        // Insert a new key for the indexed values which points back to the row being indexed. Any existing key for
+        // an updated row has already been cleared by `tuple_update`, and Postgres only calls us for rows which
+        // match the predicate of a partial index.
         let values = from_raw_parts_mut(raw_values, natts);
         let isnull = from_raw_parts_mut(raw_isnull, natts);
 
-        let key = build_key_from_index_values(index_oid, id, natts, attrs, values, isnull);
+        let key =
+            build_key_from_index_values(relfilenumber, id, natts, attrs, options, values, isnull);
         txn.set(&key, &[]);
 
         true
     }
 }
 
+// Builds the index key for a table row, evaluating any index expressions. Returns `None` if the row doesn't match
+// the predicate of a partial index, in which case it has no key in the index.
 pub fn build_key_from_table_tuple(
-    index_oid: Oid,
     row_id: u32,
     index_rel: Relation,
     table_slot: *mut TupleTableSlot,
     index_info: *mut pg_sys::IndexInfo,
-) -> Vec<u8> {
+) -> Option<Vec<u8>> {
     // Get index tuple descriptor
     let index_tuple_desc = unsafe { (*index_rel).rd_att };
+    let relfilenumber = unsafe { (*index_rel).rd_locator.relNumber };
     let natts = unsafe { (*index_tuple_desc).natts as usize };
 
     // Create a new slot for the index tuple
@@ -144,52 +617,97 @@ pub fn build_key_from_table_tuple(
     };
 
     // Generate the index tuple values from the heap tuple
-    unsafe {
-        // Create a new estate
+    let matches_predicate = unsafe {
+        // Create a new estate. Index expressions and predicates are evaluated against the table row, so it has
+        // to be set as the scan tuple of the expression context.
         let estate = pgrx::pg_sys::CreateExecutorState();
+        let econtext = pgrx::pg_sys::MakePerTupleExprContext(estate);
+        (*econtext).ecxt_scantuple = table_slot;
 
-        pgrx::pg_sys::FormIndexDatum(
-            index_info,
-            table_slot,
-            estate,
-            (*index_slot).tts_values,
-            (*index_slot).tts_isnull,
-        );
+        let matches_predicate = predicate_matches(index_info, estate, econtext);
+        if matches_predicate {
+            pgrx::pg_sys::FormIndexDatum(
+                index_info,
+                table_slot,
+                estate,
+                (*index_slot).tts_values,
+                (*index_slot).tts_isnull,
+            );
+        }
 
-        // Free the estate
+        // Free the estate. The prepared index expressions live in its memory, so they must be prepared again
+        // the next time the index info is used.
         pgrx::pg_sys::FreeExecutorState(estate);
+        (*index_info).ii_ExpressionsState = std::ptr::null_mut();
+
+        matches_predicate
+    };
+
+    if !matches_predicate {
+        unsafe { pgrx::pg_sys::ExecDropSingleTupleTableSlot(index_slot) };
+        return None;
     }
 
     // Now extract the values from the index slot and create our key
     let values = unsafe { std::slice::from_raw_parts((*index_slot).tts_values, natts) };
     let isnull = unsafe { std::slice::from_raw_parts((*index_slot).tts_isnull, natts) };
     let attrs = unsafe { (*index_tuple_desc).attrs.as_slice(natts) };
+    let options = unsafe { std::slice::from_raw_parts((*index_rel).rd_indoption, natts) };
 
-    let index_key = build_key_from_index_values(index_oid, row_id, natts, attrs, values, isnull);
+    let index_key =
+        build_key_from_index_values(relfilenumber, row_id, natts, attrs, options, values, isnull);
 
     // Free the slot
     unsafe { pgrx::pg_sys::ExecDropSingleTupleTableSlot(index_slot) };
 
-    index_key
+    Some(index_key)
+}
+
+// Checks if the row loaded into the expression context matches the predicate of a partial index. Indexes without
+// a predicate contain all rows. This works like `ExecQual`, which is inlined and not available to us, so a NULL
+// result doesn't match.
+unsafe fn predicate_matches(
+    index_info: *mut pg_sys::IndexInfo,
+    estate: *mut pg_sys::EState,
+    econtext: *mut pg_sys::ExprContext,
+) -> bool {
+    unsafe {
+        if (*index_info).ii_Predicate.is_null() {
+            return true;
+        }
+
+        let predicate = pg_sys::ExecPrepareQual((*index_info).ii_Predicate, estate);
+        let evaluate = (*predicate).evalfunc.unwrap();
+
+        let mut isnull = false;
+        let result = PgMemoryContexts::For((*econtext).ecxt_per_tuple_memory)
+            .switch_to(|_| evaluate(predicate, econtext, &mut isnull));
+
+        !isnull && bool::from_datum(result, false).unwrap_or(false)
+    }
 }
 
 pub fn build_key_from_index_values(
-    index_oid: Oid,
+    relfilenumber: RelFileNumber,
     id: u32,
     natts: usize,
     attrs: &[FormData_pg_attribute],
+    options: &[i16],
     values: &[Datum],
     isnull: &[bool],
 ) -> Vec<u8> {
-    let index_subspace = crate::subspace::index(index_oid);
+    let index_subspace = crate::subspace::index(relfilenumber);
 
     // Prepare tuple elements for the index key
     let mut key_elements = Vec::with_capacity(natts);
 
     for i in 0..natts {
+        // The DESC and NULLS FIRST/LAST options of the column decide how its values are ordered
+        let order = ColumnOrder::from_index_option(options[i]);
+
         if isnull[i] {
             // For NULL values, we'll use a special marker in the tuple
-            key_elements.push(foundationdb::tuple::Element::Nil);
+            key_elements.push(order.null());
         } else {
             // Get the attribute type OID
             let attr = attrs[i];
@@ -200,8 +718,8 @@ pub fn build_key_from_index_values(
 
             // Encode the datum using our helper function
             // This will convert the Postgres datum to an FDB tuple element
-            let element = encode_datum_for_index(datum, type_oid);
-            key_elements.push(element);
+            let element = encode_datum_for_index(datum, type_oid, attr.attcollation);
+            key_elements.push(order.encode(element));
         }
     }
 
@@ -211,3 +729,9 @@ pub fn build_key_from_index_values(
     // Create the key using the subspace and key elements (which now includes the ID)
     index_subspace.pack(&key_elements)
 }
+
+// Extracts the ID of the row an index key points to, which is the last element of the key
+pub fn row_id_from_index_key(key: &[u8]) -> Option<u32> {
+    let elements: Vec<Element> = unpack(key).ok()?;
+    u32::try_from(elements.last()?.as_i64()?).ok()
+}
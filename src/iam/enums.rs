@@ -0,0 +1,132 @@
+use std::ffi::c_char;
+
+use pg_sys::{
+    AlterEnumStmt, DestReceiver, FormData_pg_enum, Oid, ParamListInfo, PlannedStmt,
+    ProcessUtility_hook_type, QueryCompletion, QueryEnvironment,
+};
+use pgrx::{pg_sys::panic::ErrorReportable, prelude::*};
+
+use crate::iam::{
+    utils::{type_contains, type_name},
+    validate::{for_each_in_sys_cache_list, get_struct},
+};
+
+static mut PREV_PROCESS_UTILITY_HOOK: ProcessUtility_hook_type = None;
+
+pub fn init() {
+    unsafe {
+        PREV_PROCESS_UTILITY_HOOK = pg_sys::ProcessUtility_hook;
+        pg_sys::ProcessUtility_hook = Some(process_utility);
+    }
+}
+
+// Indexes store enum values by their sort order (see `encode_enum`). Adding a value with `ALTER TYPE ... ADD VALUE
+// BEFORE/AFTER` usually gives it a sort order between its neighbours, but once there's no room left, Postgres
+// renumbers the existing values, which would leave the keys of indexes on the enum out of order. We compare the sort
+// orders from before and after adding the value, and raise an error if they changed while a pgfdb index depends on
+// the enum, which rolls back the renumbering.
+#[pg_guard]
+unsafe extern "C-unwind" fn process_utility(
+    pstmt: *mut PlannedStmt,
+    query_string: *const c_char,
+    read_only_tree: bool,
+    context: pg_sys::ProcessUtilityContext::Type,
+    params: ParamListInfo,
+    query_env: *mut QueryEnvironment,
+    dest: *mut DestReceiver,
+    qc: *mut QueryCompletion,
+) {
+    unsafe {
+        let dependent =
+            added_enum_value_with_neighbour((*pstmt).utilityStmt).and_then(|enum_oid| {
+                dependent_index(enum_oid).map(|index| (enum_oid, index, sort_orders(enum_oid)))
+            });
+
+        let prev_hook = PREV_PROCESS_UTILITY_HOOK.unwrap_or(pg_sys::standard_ProcessUtility);
+        prev_hook(
+            pstmt,
+            query_string,
+            read_only_tree,
+            context,
+            params,
+            query_env,
+            dest,
+            qc,
+        );
+
+        let Some((enum_oid, index, sort_orders_before)) = dependent else {
+            return;
+        };
+
+        // Make the changes to the enum visible to the catalog cache
+        pg_sys::CommandCounterIncrement();
+        let sort_orders_after = sort_orders(enum_oid);
+        if sort_orders_before
+            .iter()
+            .any(|value| !sort_orders_after.contains(value))
+        {
+            ereport!(
+                ERROR,
+                PgSqlErrorCode::ERRCODE_FEATURE_NOT_SUPPORTED,
+                &format!(
+                    "adding this value renumbers the values of enum {}, which pgfdb index {} depends on",
+                    type_name(enum_oid),
+                    index
+                )
+            );
+        }
+    }
+}
+
+// Returns the enum a statement adds a value to, if the value is placed BEFORE or AFTER an existing one. Values
+// added at the end of an enum never cause a renumbering.
+unsafe fn added_enum_value_with_neighbour(node: *mut pg_sys::Node) -> Option<Oid> {
+    unsafe {
+        if node.is_null() || (*node).type_ != pg_sys::NodeTag::T_AlterEnumStmt {
+            return None;
+        }
+
+        let stmt = node as *mut AlterEnumStmt;
+        if (*stmt).newVal.is_null() || (*stmt).newValNeighbor.is_null() {
+            return None;
+        }
+
+        let type_name = pg_sys::makeTypeNameFromNameList((*stmt).typeName);
+        Some(pg_sys::typenameTypeId(std::ptr::null_mut(), type_name))
+    }
+}
+
+// Returns the name of a pgfdb index with a column which contains values of the enum, if there is one
+fn dependent_index(enum_oid: Oid) -> Option<String> {
+    let (names, types): (Option<Vec<String>>, Option<Vec<Oid>>) = Spi::get_two(
+        "SELECT array_agg(c.oid::regclass::text), array_agg(a.atttypid)
+        FROM pg_class c
+        JOIN pg_am am ON am.oid = c.relam
+        JOIN pg_attribute a ON a.attrelid = c.oid
+        WHERE am.amname = 'pgfdb'",
+    )
+    .unwrap_or_report();
+
+    names
+        .unwrap_or_default()
+        .into_iter()
+        .zip(types.unwrap_or_default())
+        .find(|(_, type_oid)| type_contains(*type_oid, enum_oid))
+        .map(|(name, _)| name)
+}
+
+// Returns the OID and sort order of each value of an enum
+unsafe fn sort_orders(enum_oid: Oid) -> Vec<(Oid, f32)> {
+    unsafe {
+        let mut sort_orders = Vec::new();
+        for_each_in_sys_cache_list(
+            pg_sys::SysCacheIdentifier::ENUMTYPOIDNAME,
+            enum_oid,
+            |tuple| {
+                let value = &*get_struct::<FormData_pg_enum>(tuple);
+                sort_orders.push((value.oid, value.enumsortorder));
+            },
+        );
+        sort_orders
+    }
+}
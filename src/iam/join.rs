@@ -0,0 +1,667 @@
+use std::ffi::c_void;
+
+use foundationdb::tuple::Subspace;
+use foundationdb::{FdbResult, RangeOption};
+use futures::StreamExt;
+use futures::stream::BoxStream;
+use pg_sys::{
+    CustomExecMethods, CustomPath, CustomPathMethods, CustomScan, CustomScanMethods,
+    CustomScanState, EState, ExplainState, IndexOptInfo, JoinPathExtraData, JoinType, List, Node,
+    NodeTag, Oid, OpExpr, Plan, PlannerInfo, RelOptInfo, RestrictInfo, ScanState, TargetEntry,
+    TupleTableSlot, Var, set_join_pathlist_hook_type,
+};
+use pgrx::list::List as PgList;
+use pgrx::memcx::current_context;
+use pgrx::prelude::*;
+use pollster::FutureExt;
+
+use crate::errors::FdbErrorExt;
+use crate::iam::scan::{CONCURRENT_RANGE_READS, IndexEntry, RowLookup, read_ranges};
+use crate::iam::utils::{ColumnOrder, encode_datum_for_index};
+
+// Number of outer rows whose index lookups are issued together
+const JOIN_BATCH_SIZE: usize = 100;
+
+static mut PREV_SET_JOIN_PATHLIST_HOOK: set_join_pathlist_hook_type = None;
+
+static mut PATH_METHODS: CustomPathMethods = CustomPathMethods {
+    CustomName: c"PgfdbBatchedNestLoop".as_ptr(),
+    PlanCustomPath: Some(plan_batched_join),
+    ReparameterizeCustomPathByChild: None,
+};
+
+static mut SCAN_METHODS: CustomScanMethods = CustomScanMethods {
+    CustomName: c"PgfdbBatchedNestLoop".as_ptr(),
+    CreateCustomScanState: Some(create_batched_join_state),
+};
+
+static mut EXEC_METHODS: CustomExecMethods = CustomExecMethods {
+    CustomName: c"PgfdbBatchedNestLoop".as_ptr(),
+    BeginCustomScan: Some(begin_batched_join),
+    ExecCustomScan: Some(exec_batched_join),
+    EndCustomScan: Some(end_batched_join),
+    ReScanCustomScan: Some(rescan_batched_join),
+    MarkPosCustomScan: None,
+    RestrPosCustomScan: None,
+    EstimateDSMCustomScan: None,
+    InitializeDSMCustomScan: None,
+    ReInitializeDSMCustomScan: None,
+    InitializeWorkerCustomScan: None,
+    ShutdownCustomScan: None,
+    ExplainCustomScan: Some(explain_batched_join),
+};
+
+pub fn init() {
+    unsafe {
+        PREV_SET_JOIN_PATHLIST_HOOK = pg_sys::set_join_pathlist_hook;
+        pg_sys::set_join_pathlist_hook = Some(set_join_pathlist);
+        pg_sys::RegisterCustomScanMethods(&raw const SCAN_METHODS);
+    }
+}
+
+// A batched nested loop joins an outer relation to a pgfdb table through an index on the join column. A regular
+// nested loop rescans the index for every outer row, which costs a round trip to FDB per row. Instead, we read a
+// batch of outer rows and issue the index range reads and table lookups for all of them at once (see
+// `read_ranges`), so a join over thousands of rows only waits for a handful of round trips per batch.
+#[repr(C)]
+struct BatchedJoinState {
+    // Must be first field to ensure proper casting
+    base: CustomScanState,
+    table_oid: Oid,
+    index_oid: Oid,
+    lookup: RowLookup,
+    // How to encode the join key of an outer row into the first column of the index
+    index_subspace: Subspace,
+    key_type: Oid,
+    key_collation: Oid,
+    key_order: ColumnOrder,
+    // Attribute number of the join key in the outer rows
+    outer_key: i16,
+    // Where each column of the scan tuple comes from, see `plan_batched_join`
+    columns: Vec<JoinColumn>,
+    // Copies of the outer rows of the current batch
+    outer_slots: Vec<*mut TupleTableSlot>,
+    inner_slot: *mut TupleTableSlot,
+    // The outer row of each range read for the current batch. Outer rows with a NULL key have no range.
+    batch_rows: Vec<usize>,
+    // Joined index entries for the current batch
+    entries: Option<BoxStream<'static, FdbResult<IndexEntry>>>,
+    outer_done: bool,
+}
+
+#[derive(Clone, Copy)]
+enum JoinColumn {
+    Outer(i16),
+    Inner(i16),
+}
+
+// Planner hook which adds a batched nested loop path for inner joins on an indexed column of a pgfdb table
+#[pg_guard]
+unsafe extern "C-unwind" fn set_join_pathlist(
+    root: *mut PlannerInfo,
+    joinrel: *mut RelOptInfo,
+    outerrel: *mut RelOptInfo,
+    innerrel: *mut RelOptInfo,
+    jointype: JoinType::Type,
+    extra: *mut JoinPathExtraData,
+) {
+    unsafe {
+        if let Some(prev_hook) = PREV_SET_JOIN_PATHLIST_HOOK {
+            prev_hook(root, joinrel, outerrel, innerrel, jointype, extra);
+        }
+
+        // This replaces a nested loop, so it's disabled along with them. Placeholders and lateral references
+        // would need to be evaluated or passed on at the right level, which we don't support.
+        if jointype != JoinType::JOIN_INNER
+            || !pg_sys::enable_nestloop
+            || !(*root).placeholder_list.is_null()
+            || !(*joinrel).lateral_relids.is_null()
+        {
+            return;
+        }
+
+        let outer_path = (*outerrel).cheapest_total_path;
+        if outer_path.is_null() || !(*outer_path).param_info.is_null() {
+            return;
+        }
+
+        let Some((index, outer_var)) = join_index(root, outerrel, innerrel, (*extra).restrictlist)
+        else {
+            return;
+        };
+
+        // The join quals and the filters on the inner table are checked for every joined row, as the inner
+        // table isn't scanned on its own
+        let clauses = pg_sys::list_concat_copy((*extra).restrictlist, (*innerrel).baserestrictinfo);
+        if !inner_columns_supported(joinrel, innerrel, clauses) {
+            return;
+        }
+
+        // Each batch of outer rows costs two round trips, one for the index ranges and one for the table rows,
+        // for every `CONCURRENT_RANGE_READS` rows. The CPU costs are like those of a nested loop.
+        let outer_rows = (*outer_path).rows;
+        let round_trips = (outer_rows / CONCURRENT_RANGE_READS as f64).ceil().max(1.0) * 2.0;
+        let rows = (*joinrel).rows;
+        let num_clauses = if clauses.is_null() {
+            0.0
+        } else {
+            (*clauses).length as f64
+        };
+
+        let mut path = PgBox::<CustomPath>::alloc_node(NodeTag::T_CustomPath);
+        path.path.pathtype = NodeTag::T_CustomScan;
+        path.path.parent = joinrel;
+        path.path.pathtarget = (*joinrel).reltarget;
+        path.path.rows = rows;
+        path.path.startup_cost = (*outer_path).startup_cost + 2.0 * pg_sys::random_page_cost;
+        path.path.total_cost = (*outer_path).total_cost
+            + round_trips * pg_sys::random_page_cost
+            + rows * (pg_sys::cpu_tuple_cost + num_clauses * pg_sys::cpu_operator_cost);
+        path.custom_paths = pg_sys::lappend(std::ptr::null_mut(), outer_path.cast());
+
+        // Paths are never copied, so the private list can point to planner structs
+        let mut private = pg_sys::lappend(std::ptr::null_mut(), index.cast());
+        private = pg_sys::lappend(private, outer_var.cast());
+        private = pg_sys::lappend(private, clauses.cast());
+        path.custom_private = private;
+
+        path.methods = &raw const PATH_METHODS;
+
+        pg_sys::add_path(joinrel, path.into_pg().cast());
+    }
+}
+
+// Finds a pgfdb index on the inner table whose first column is joined to a column of the outer relation by
+// equality. Returns the index and the outer column.
+unsafe fn join_index(
+    root: *mut PlannerInfo,
+    outerrel: *mut RelOptInfo,
+    innerrel: *mut RelOptInfo,
+    restrictlist: *mut List,
+) -> Option<(*mut IndexOptInfo, *mut Var)> {
+    unsafe {
+        if (*innerrel).reloptkind != pg_sys::RelOptKind::RELOPT_BASEREL
+            || (*innerrel).rtekind != pg_sys::RTEKind::RTE_RELATION
+            || !(*innerrel).lateral_relids.is_null()
+        {
+            return None;
+        }
+
+        // Inheritance parents are scanned through their children, which this doesn't handle
+        let rte = *(*root).simple_rte_array.add((*innerrel).relid as usize);
+        if (*rte).inh {
+            return None;
+        }
+
+        let am_oid = pg_sys::get_index_am_oid(c"pgfdb".as_ptr(), true);
+        current_context(|ctx| {
+            let indexes: PgList<*mut c_void> =
+                PgList::downcast_ptr_in_memcx((*innerrel).indexlist, ctx).unwrap();
+            let clauses: PgList<*mut c_void> =
+                PgList::downcast_ptr_in_memcx(restrictlist, ctx).unwrap();
+
+            indexes
+                .iter()
+                .map(|index| *index as *mut IndexOptInfo)
+                .filter(|index| {
+                    (**index).relam == am_oid
+                        && *(**index).indexkeys != 0
+                        && ((**index).indpred.is_null() || (**index).predOK)
+                })
+                .find_map(|index| {
+                    clauses.iter().find_map(|rinfo| {
+                        outer_join_column(*rinfo as *mut RestrictInfo, index, outerrel, innerrel)
+                            .map(|outer_var| (index, outer_var))
+                    })
+                })
+        })
+    }
+}
+
+// Checks if a join clause is an equality between the first column of the index and a column of the outer relation,
+// which can then be looked up in the index. The types and collation must match those of the index, so that the
+// value of the outer column is encoded like the keys of the index.
+unsafe fn outer_join_column(
+    rinfo: *mut RestrictInfo,
+    index: *mut IndexOptInfo,
+    outerrel: *mut RelOptInfo,
+    innerrel: *mut RelOptInfo,
+) -> Option<*mut Var> {
+    unsafe {
+        let clause = (*rinfo).clause as *mut Node;
+        if (*rinfo).pseudoconstant || (*clause).type_ != NodeTag::T_OpExpr {
+            return None;
+        }
+
+        let op = clause as *mut OpExpr;
+        let column_type = *(*index).opcintype;
+        if (*op).inputcollid != *(*index).indexcollations
+            || pg_sys::get_op_opfamily_strategy((*op).opno, *(*index).opfamily)
+                != pg_sys::BTEqualStrategyNumber as i32
+        {
+            return None;
+        }
+
+        let args: Vec<*mut Var> = current_context(|ctx| {
+            let args: PgList<*mut c_void> = PgList::downcast_ptr_in_memcx((*op).args, ctx).unwrap();
+            args.iter().map(|arg| *arg as *mut Var).collect()
+        });
+        let [left, right] = args[..] else {
+            return None;
+        };
+
+        let is_var = |var: *mut Var| {
+            (*var).xpr.type_ == NodeTag::T_Var
+                && (*var).varlevelsup == 0
+                && (*var).vartype == column_type
+        };
+        let is_inner_key = |var: *mut Var| {
+            is_var(var)
+                && (*var).varno == (*innerrel).relid as i32
+                && (*var).varattno as i32 == *(*index).indexkeys
+        };
+        let is_outer = |var: *mut Var| {
+            is_var(var)
+                && (*var).varattno > 0
+                && pg_sys::bms_is_member((*var).varno, (*outerrel).relids)
+        };
+
+        if is_inner_key(left) && is_outer(right) {
+            Some(right)
+        } else if is_inner_key(right) && is_outer(left) {
+            Some(left)
+        } else {
+            None
+        }
+    }
+}
+
+// Rows of the inner table are decoded from FDB, so only its user columns are available, not whole-row references
+// or system columns
+unsafe fn inner_columns_supported(
+    joinrel: *mut RelOptInfo,
+    innerrel: *mut RelOptInfo,
+    clauses: *mut List,
+) -> bool {
+    unsafe {
+        let quals = pg_sys::extract_actual_clauses(clauses, false);
+        let exprs = pg_sys::list_concat_copy((*(*joinrel).reltarget).exprs, quals);
+        let vars = pg_sys::pull_var_clause(exprs.cast(), 0);
+
+        current_context(|ctx| {
+            let vars: PgList<*mut c_void> = PgList::downcast_ptr_in_memcx(vars, ctx).unwrap();
+            vars.iter()
+                .map(|var| *var as *mut Var)
+                .all(|var| (*var).varno != (*innerrel).relid as i32 || (*var).varattno > 0)
+        })
+    }
+}
+
+// Creates the plan for a batched nested loop. The executor builds a scan tuple with all the columns needed by the
+// target list and quals, taken either from the outer row or the inner row, and Postgres evaluates the quals and
+// projection on top of it. The private list stores the table, the index, the attribute number of the join key in
+// the outer rows and where each column of the scan tuple comes from.
+#[pg_guard]
+unsafe extern "C-unwind" fn plan_batched_join(
+    root: *mut PlannerInfo,
+    _rel: *mut RelOptInfo,
+    best_path: *mut CustomPath,
+    tlist: *mut List,
+    _clauses: *mut List,
+    custom_plans: *mut List,
+) -> *mut Plan {
+    unsafe {
+        let (index, outer_var, clauses, outer_plan) = current_context(|ctx| {
+            let private: PgList<*mut c_void> =
+                PgList::downcast_ptr_in_memcx((*best_path).custom_private, ctx).unwrap();
+            let plans: PgList<*mut c_void> =
+                PgList::downcast_ptr_in_memcx(custom_plans, ctx).unwrap();
+            (
+                *private.get(0).unwrap() as *mut IndexOptInfo,
+                *private.get(1).unwrap() as *mut Var,
+                *private.get(2).unwrap() as *mut List,
+                *plans.get(0).unwrap() as *mut Plan,
+            )
+        });
+
+        let inner_relid = (*(*index).rel).relid as i32;
+        let table_oid = (**(*root).simple_rte_array.add(inner_relid as usize)).relid;
+        let quals = pg_sys::extract_actual_clauses(clauses, false);
+
+        let vars = pg_sys::pull_var_clause(pg_sys::list_concat_copy(tlist, quals).cast(), 0);
+        let scan_tlist = pg_sys::add_to_flat_tlist(std::ptr::null_mut(), vars);
+
+        let mut private = pg_sys::lappend_int(std::ptr::null_mut(), table_oid.to_u32() as i32);
+        private = pg_sys::lappend_int(private, (*index).indexoid.to_u32() as i32);
+        private = pg_sys::lappend_int(private, outer_column(outer_plan, outer_var) as i32);
+
+        let scan_vars: Vec<*mut Var> = current_context(|ctx| {
+            let entries: PgList<*mut c_void> =
+                PgList::downcast_ptr_in_memcx(scan_tlist, ctx).unwrap();
+            entries
+                .iter()
+                .map(|entry| (*(*entry as *mut TargetEntry)).expr as *mut Var)
+                .collect()
+        });
+        for var in scan_vars {
+            let (is_inner, attno) = if (*var).varno == inner_relid {
+                (1, (*var).varattno)
+            } else {
+                (0, outer_column(outer_plan, var))
+            };
+            private = pg_sys::lappend_int(private, is_inner);
+            private = pg_sys::lappend_int(private, attno as i32);
+        }
+
+        let mut cscan = PgBox::<CustomScan>::alloc_node(NodeTag::T_CustomScan);
+        cscan.scan.plan.targetlist = tlist;
+        cscan.scan.plan.qual = quals;
+        cscan.scan.scanrelid = 0;
+        cscan.flags = (*best_path).flags;
+        cscan.custom_plans = custom_plans;
+        cscan.custom_private = private;
+        cscan.custom_scan_tlist = scan_tlist;
+
+        cscan.methods = &raw const SCAN_METHODS;
+
+        cscan.into_pg().cast()
+    }
+}
+
+// Finds the attribute number of a column of the outer relation in the rows returned by the outer plan
+unsafe fn outer_column(outer_plan: *mut Plan, var: *mut Var) -> i16 {
+    unsafe {
+        let resno = current_context(|ctx| {
+            let entries: PgList<*mut c_void> =
+                PgList::downcast_ptr_in_memcx((*outer_plan).targetlist, ctx).unwrap();
+            entries.iter().find_map(|entry| {
+                let entry = *entry as *mut TargetEntry;
+                let expr = (*entry).expr as *mut Var;
+                ((*expr).xpr.type_ == NodeTag::T_Var
+                    && (*expr).varno == (*var).varno
+                    && (*expr).varattno == (*var).varattno)
+                    .then_some((*entry).resno)
+            })
+        });
+
+        resno.unwrap_or_else(|| {
+            error!(
+                "column {} of relation {} is missing from the outer plan of a batched join",
+                (*var).varattno,
+                (*var).varno
+            )
+        })
+    }
+}
+
+unsafe extern "C-unwind" fn create_batched_join_state(cscan: *mut CustomScan) -> *mut Node {
+    unsafe {
+        let mut state = PgBox::<BatchedJoinState>::alloc0();
+        state.base.ss.ps.type_ = NodeTag::T_CustomScanState;
+        state.base.flags = (*cscan).flags;
+
+        state.base.methods = &raw const EXEC_METHODS;
+
+        // The Rust fields are initialized in `begin_batched_join`. We must use ptr::write to avoid dropping
+        // uninitialized memory.
+        let state_pointer = state.as_ptr();
+        std::ptr::write(&mut (*state_pointer).index_subspace, Subspace::all());
+        std::ptr::write(&mut (*state_pointer).columns, Vec::new());
+        std::ptr::write(&mut (*state_pointer).outer_slots, Vec::new());
+        std::ptr::write(&mut (*state_pointer).batch_rows, Vec::new());
+        std::ptr::write(&mut (*state_pointer).entries, None);
+
+        state.into_pg().cast()
+    }
+}
+
+#[pg_guard]
+unsafe extern "C-unwind" fn begin_batched_join(
+    node: *mut CustomScanState,
+    estate: *mut EState,
+    eflags: i32,
+) {
+    unsafe {
+        log!("JOIN: Begin batched nested loop");
+
+        let state = &mut *(node as *mut BatchedJoinState);
+        let cscan = state.base.ss.ps.plan as *mut CustomScan;
+
+        let (private, outer_plan) = current_context(|ctx| {
+            let private: PgList<i32> =
+                PgList::downcast_ptr_in_memcx((*cscan).custom_private, ctx).unwrap();
+            let plans: PgList<*mut c_void> =
+                PgList::downcast_ptr_in_memcx((*cscan).custom_plans, ctx).unwrap();
+            (
+                private.iter().copied().collect::<Vec<i32>>(),
+                *plans.get(0).unwrap() as *mut Plan,
+            )
+        });
+
+        state.table_oid = Oid::from(private[0] as u32);
+        state.index_oid = Oid::from(private[1] as u32);
+        state.outer_key = private[2] as i16;
+        state.columns = private[3..]
+            .chunks(2)
+            .map(|column| match column {
+                [1, attno] => JoinColumn::Inner(*attno as i16),
+                [_, attno] => JoinColumn::Outer(*attno as i16),
+                _ => unreachable!(),
+            })
+            .collect();
+
+        // The join key is looked up in the first column of the index
+        let index_rel =
+            pg_sys::index_open(state.index_oid, pg_sys::AccessShareLock as pg_sys::LOCKMODE);
+        let key_attr = (*(*index_rel).rd_att).attrs.as_slice(1)[0];
+        state.index_subspace = crate::subspace::index((*index_rel).rd_locator.relNumber);
+        state.key_type = key_attr.atttypid;
+        state.key_collation = key_attr.attcollation;
+        state.key_order = ColumnOrder::from_index_option(*(*index_rel).rd_indoption);
+        state.lookup = RowLookup::new(state.table_oid, index_rel);
+        pg_sys::index_close(index_rel, pg_sys::NoLock as pg_sys::LOCKMODE);
+
+        let outer = pg_sys::ExecInitNode(outer_plan, estate, eflags);
+        state.base.custom_ps = pg_sys::lappend(std::ptr::null_mut(), outer.cast());
+
+        // Slots are allocated in the tuple table of the executor, which drops them at the end of the query
+        let outer_desc = pg_sys::ExecGetResultType(outer);
+        state.outer_slots = (0..JOIN_BATCH_SIZE)
+            .map(|_| {
+                pg_sys::ExecAllocTableSlot(
+                    &mut (*estate).es_tupleTable,
+                    outer_desc,
+                    &pg_sys::TTSOpsMinimalTuple,
+                )
+            })
+            .collect();
+
+        let table_rel = pg_sys::RelationIdGetRelation(state.table_oid);
+        let table_desc = pg_sys::CreateTupleDescCopy((*table_rel).rd_att);
+        pg_sys::RelationClose(table_rel);
+        state.inner_slot = pg_sys::ExecAllocTableSlot(
+            &mut (*estate).es_tupleTable,
+            table_desc,
+            &pg_sys::TTSOpsVirtual,
+        );
+    }
+}
+
+#[pg_guard]
+unsafe extern "C-unwind" fn exec_batched_join(node: *mut CustomScanState) -> *mut TupleTableSlot {
+    unsafe {
+        pg_sys::ExecScan(
+            &mut (*node).ss,
+            Some(next_joined_row),
+            Some(recheck_joined_row),
+        )
+    }
+}
+
+// Returns the next joined row as a scan tuple, reading the next batch of outer rows once the entries of the
+// current one have run out
+#[pg_guard]
+unsafe extern "C-unwind" fn next_joined_row(node: *mut ScanState) -> *mut TupleTableSlot {
+    unsafe {
+        let state = &mut *(node as *mut BatchedJoinState);
+        let scan_slot = state.base.ss.ss_ScanTupleSlot;
+
+        loop {
+            if let Some(entries) = state.entries.as_mut() {
+                match entries.next().block_on() {
+                    Some(entry) => {
+                        let entry = entry.unwrap_or_pg_error();
+                        let outer_slot = state.outer_slots[state.batch_rows[entry.range]];
+
+                        // The inner row only has to live until the next row is fetched, so it's decoded in the
+                        // per-tuple memory which `ExecScan` resets
+                        let econtext = state.base.ss.ps.ps_ExprContext;
+                        let mut tuple = crate::coding::Tuple::deserialize(&entry.row);
+                        PgMemoryContexts::For((*econtext).ecxt_per_tuple_memory)
+                            .switch_to(|_| tuple.load_into_tts(&mut *state.inner_slot));
+
+                        return store_joined_row(state, outer_slot);
+                    }
+                    None => state.entries = None,
+                }
+            }
+
+            if state.outer_done {
+                return pg_sys::ExecClearTuple(scan_slot);
+            }
+            read_outer_batch(state);
+        }
+    }
+}
+
+// Reads the next batch of outer rows and starts reading the index entries and table rows for their join keys
+unsafe fn read_outer_batch(state: &mut BatchedJoinState) {
+    unsafe {
+        let outer = outer_plan_state(state);
+
+        let mut ranges: Vec<RangeOption<'static>> = Vec::with_capacity(JOIN_BATCH_SIZE);
+        state.batch_rows.clear();
+
+        for (row, &outer_slot) in state.outer_slots.iter().enumerate() {
+            let slot = pg_sys::ExecProcNode(outer);
+            if slot.is_null() || (*slot).tts_flags & pg_sys::TTS_FLAG_EMPTY as u16 != 0 {
+                state.outer_done = true;
+                break;
+            }
+            pg_sys::ExecCopySlot(outer_slot, slot);
+
+            // NULL keys never join with anything
+            let mut isnull = false;
+            let key = pg_sys::slot_getattr(outer_slot, state.outer_key as i32, &mut isnull);
+            if isnull {
+                continue;
+            }
+
+            let element = encode_datum_for_index(key, state.key_type, state.key_collation);
+            let subspace = state
+                .index_subspace
+                .subspace(&vec![state.key_order.encode(element)]);
+            ranges.push(RangeOption::from(subspace.range()));
+            state.batch_rows.push(row);
+        }
+
+        log!("JOIN: Read batch with {} join keys", ranges.len());
+        if !ranges.is_empty() {
+            state.entries = Some(read_ranges(state.lookup, &ranges, false, None, false));
+        }
+    }
+}
+
+// The outer plan is initialized in `begin_batched_join` and is the only child of the node
+unsafe fn outer_plan_state(state: &BatchedJoinState) -> *mut pg_sys::PlanState {
+    unsafe {
+        current_context(|ctx| {
+            let children: PgList<*mut c_void> =
+                PgList::downcast_ptr_in_memcx(state.base.custom_ps, ctx).unwrap();
+            *children.get(0).unwrap() as *mut pg_sys::PlanState
+        })
+    }
+}
+
+// Stores the columns of the scan tuple from the outer row and the inner row loaded into the inner slot
+unsafe fn store_joined_row(
+    state: &mut BatchedJoinState,
+    outer_slot: *mut TupleTableSlot,
+) -> *mut TupleTableSlot {
+    unsafe {
+        let scan_slot = state.base.ss.ss_ScanTupleSlot;
+        pg_sys::ExecClearTuple(scan_slot);
+
+        for (i, column) in state.columns.iter().enumerate() {
+            let (slot, attno) = match *column {
+                JoinColumn::Outer(attno) => (outer_slot, attno),
+                JoinColumn::Inner(attno) => (state.inner_slot, attno),
+            };
+            let mut isnull = false;
+            *(*scan_slot).tts_values.add(i) = pg_sys::slot_getattr(slot, attno as i32, &mut isnull);
+            *(*scan_slot).tts_isnull.add(i) = isnull;
+        }
+
+        pg_sys::ExecStoreVirtualTuple(scan_slot)
+    }
+}
+
+// Joined rows are checked against the quals by `ExecScan`, so there is nothing left to recheck
+unsafe extern "C-unwind" fn recheck_joined_row(
+    _node: *mut ScanState,
+    _slot: *mut TupleTableSlot,
+) -> bool {
+    true
+}
+
+#[pg_guard]
+unsafe extern "C-unwind" fn rescan_batched_join(node: *mut CustomScanState) {
+    unsafe {
+        let state = &mut *(node as *mut BatchedJoinState);
+        let outer = outer_plan_state(state);
+
+        pg_sys::ExecReScan(outer);
+        state.entries = None;
+        state.batch_rows.clear();
+        state.outer_done = false;
+    }
+}
+
+#[pg_guard]
+unsafe extern "C-unwind" fn end_batched_join(node: *mut CustomScanState) {
+    log!("JOIN: End batched nested loop");
+
+    unsafe {
+        let state = node as *mut BatchedJoinState;
+        pg_sys::ExecEndNode(outer_plan_state(&*state));
+
+        // Take ownership of the Rust fields to drop them
+        drop(std::ptr::read(&(*state).entries));
+        drop(std::ptr::read(&(*state).index_subspace));
+        drop(std::ptr::read(&(*state).columns));
+        drop(std::ptr::read(&(*state).outer_slots));
+        drop(std::ptr::read(&(*state).batch_rows));
+    }
+}
+
+#[pg_guard]
+unsafe extern "C-unwind" fn explain_batched_join(
+    node: *mut CustomScanState,
+    _ancestors: *mut List,
+    es: *mut ExplainState,
+) {
+    unsafe {
+        let state = node as *mut BatchedJoinState;
+        pg_sys::ExplainPropertyText(
+            c"Index Name".as_ptr(),
+            pg_sys::get_rel_name((*state).index_oid),
+            es,
+        );
+        pg_sys::ExplainPropertyInteger(
+            c"Batch Size".as_ptr(),
+            std::ptr::null(),
+            JOIN_BATCH_SIZE as i64,
+            es,
+        );
+    }
+}
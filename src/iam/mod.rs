@@ -1,6 +1,12 @@
 pub(crate) mod build;
-mod scan;
+pub(crate) mod enums;
+pub(crate) mod join;
+pub(crate) mod scan;
+pub(crate) mod state;
 mod utils;
+pub(crate) mod vacuum;
+mod validate;
+mod verify;
 
 use pg_sys::{Datum, IndexAmRoutine, InvalidOid, bytea};
 use pgrx::callconv::BoxRet;
@@ -24,6 +30,7 @@ use std::ptr;
     CREATE OPERATOR FAMILY pgfdb_integer_ops USING pgfdb;
     CREATE OPERATOR FAMILY pgfdb_text_ops USING pgfdb;
     CREATE OPERATOR FAMILY pgfdb_float_ops USING pgfdb;
+    CREATE OPERATOR FAMILY pgfdb_numeric_ops USING pgfdb;
 
     -- Operator classes for integer types
     CREATE OPERATOR CLASS pgfdb_integer
@@ -34,7 +41,8 @@ use std::ptr;
     OPERATOR 3 = (INTEGER, INTEGER),
     OPERATOR 4 >= (INTEGER, INTEGER),
     OPERATOR 5 > (INTEGER, INTEGER),
-    OPERATOR 6 != (INTEGER, INTEGER);
+    OPERATOR 6 != (INTEGER, INTEGER),
+    FUNCTION 1 btint4cmp(INTEGER, INTEGER);
 
     CREATE OPERATOR CLASS pgfdb_bigint
     DEFAULT FOR TYPE BIGINT USING pgfdb
@@ -44,7 +52,8 @@ use std::ptr;
     OPERATOR 3 = (BIGINT, BIGINT),
     OPERATOR 4 >= (BIGINT, BIGINT),
     OPERATOR 5 > (BIGINT, BIGINT),
-    OPERATOR 6 != (BIGINT, BIGINT);
+    OPERATOR 6 != (BIGINT, BIGINT),
+    FUNCTION 1 btint8cmp(BIGINT, BIGINT);
 
     CREATE OPERATOR CLASS pgfdb_smallint
     DEFAULT FOR TYPE SMALLINT USING pgfdb
@@ -54,14 +63,33 @@ use std::ptr;
     OPERATOR 3 = (SMALLINT, SMALLINT),
     OPERATOR 4 >= (SMALLINT, SMALLINT),
     OPERATOR 5 > (SMALLINT, SMALLINT),
-    OPERATOR 6 != (SMALLINT, SMALLINT);
+    OPERATOR 6 != (SMALLINT, SMALLINT),
+    FUNCTION 1 btint2cmp(SMALLINT, SMALLINT);
+
+    -- Operator class for numeric type. Postgres has no comparison operators between integers and numerics, and
+    -- instead casts the integer to a numeric, so numerics have their own family.
+    CREATE OPERATOR CLASS pgfdb_numeric
+    DEFAULT FOR TYPE NUMERIC USING pgfdb
+    FAMILY pgfdb_numeric_ops AS
+    OPERATOR 1 < (NUMERIC, NUMERIC),
+    OPERATOR 2 <= (NUMERIC, NUMERIC),
+    OPERATOR 3 = (NUMERIC, NUMERIC),
+    OPERATOR 4 >= (NUMERIC, NUMERIC),
+    OPERATOR 5 > (NUMERIC, NUMERIC),
+    OPERATOR 6 != (NUMERIC, NUMERIC),
+    FUNCTION 1 numeric_cmp(NUMERIC, NUMERIC);
 
     -- Operator class for text type
     CREATE OPERATOR CLASS pgfdb_text
     DEFAULT FOR TYPE TEXT USING pgfdb
     FAMILY pgfdb_text_ops AS
+    OPERATOR 1 < (TEXT, TEXT),
+    OPERATOR 2 <= (TEXT, TEXT),
     OPERATOR 3 = (TEXT, TEXT),
-    OPERATOR 6 != (TEXT, TEXT);
+    OPERATOR 4 >= (TEXT, TEXT),
+    OPERATOR 5 > (TEXT, TEXT),
+    OPERATOR 6 != (TEXT, TEXT),
+    FUNCTION 1 bttextcmp(TEXT, TEXT);
 
     -- Operator classes for floating point types
     CREATE OPERATOR CLASS pgfdb_real
@@ -72,7 +100,8 @@ use std::ptr;
     OPERATOR 3 = (REAL, REAL),
     OPERATOR 4 >= (REAL, REAL),
     OPERATOR 5 > (REAL, REAL),
-    OPERATOR 6 != (REAL, REAL);
+    OPERATOR 6 != (REAL, REAL),
+    FUNCTION 1 btfloat4cmp(REAL, REAL);
 
     CREATE OPERATOR CLASS pgfdb_double_precision
     DEFAULT FOR TYPE DOUBLE PRECISION USING pgfdb
@@ -82,7 +111,8 @@ use std::ptr;
     OPERATOR 3 = (DOUBLE PRECISION, DOUBLE PRECISION),
     OPERATOR 4 >= (DOUBLE PRECISION, DOUBLE PRECISION),
     OPERATOR 5 > (DOUBLE PRECISION, DOUBLE PRECISION),
-    OPERATOR 6 != (DOUBLE PRECISION, DOUBLE PRECISION);
+    OPERATOR 6 != (DOUBLE PRECISION, DOUBLE PRECISION),
+    FUNCTION 1 btfloat8cmp(DOUBLE PRECISION, DOUBLE PRECISION);
 
     -- Operator class for UUID type
     CREATE OPERATOR FAMILY pgfdb_uuid_ops USING pgfdb;
@@ -90,9 +120,178 @@ use std::ptr;
     CREATE OPERATOR CLASS pgfdb_uuid
     DEFAULT FOR TYPE UUID USING pgfdb
     FAMILY pgfdb_uuid_ops AS
+    OPERATOR 1 < (UUID, UUID),
+    OPERATOR 2 <= (UUID, UUID),
     OPERATOR 3 = (UUID, UUID),
-    OPERATOR 6 != (UUID, UUID);
+    OPERATOR 4 >= (UUID, UUID),
+    OPERATOR 5 > (UUID, UUID),
+    OPERATOR 6 != (UUID, UUID),
+    FUNCTION 1 uuid_cmp(UUID, UUID);
     
+    -- Operator class for boolean type
+    CREATE OPERATOR FAMILY pgfdb_boolean_ops USING pgfdb;
+
+    CREATE OPERATOR CLASS pgfdb_boolean
+    DEFAULT FOR TYPE BOOLEAN USING pgfdb
+    FAMILY pgfdb_boolean_ops AS
+    OPERATOR 1 < (BOOLEAN, BOOLEAN),
+    OPERATOR 2 <= (BOOLEAN, BOOLEAN),
+    OPERATOR 3 = (BOOLEAN, BOOLEAN),
+    OPERATOR 4 >= (BOOLEAN, BOOLEAN),
+    OPERATOR 5 > (BOOLEAN, BOOLEAN),
+    OPERATOR 6 != (BOOLEAN, BOOLEAN),
+    FUNCTION 1 btboolcmp(BOOLEAN, BOOLEAN);
+
+    -- Operator class for bytea type
+    CREATE OPERATOR FAMILY pgfdb_bytea_ops USING pgfdb;
+
+    CREATE OPERATOR CLASS pgfdb_bytea
+    DEFAULT FOR TYPE BYTEA USING pgfdb
+    FAMILY pgfdb_bytea_ops AS
+    OPERATOR 1 < (BYTEA, BYTEA),
+    OPERATOR 2 <= (BYTEA, BYTEA),
+    OPERATOR 3 = (BYTEA, BYTEA),
+    OPERATOR 4 >= (BYTEA, BYTEA),
+    OPERATOR 5 > (BYTEA, BYTEA),
+    OPERATOR 6 != (BYTEA, BYTEA),
+    FUNCTION 1 byteacmp(BYTEA, BYTEA);
+
+    -- Operator class for the single byte \"char\" type
+    CREATE OPERATOR FAMILY pgfdb_char_ops USING pgfdb;
+
+    CREATE OPERATOR CLASS pgfdb_char
+    DEFAULT FOR TYPE \"char\" USING pgfdb
+    FAMILY pgfdb_char_ops AS
+    OPERATOR 1 < (\"char\", \"char\"),
+    OPERATOR 2 <= (\"char\", \"char\"),
+    OPERATOR 3 = (\"char\", \"char\"),
+    OPERATOR 4 >= (\"char\", \"char\"),
+    OPERATOR 5 > (\"char\", \"char\"),
+    OPERATOR 6 != (\"char\", \"char\"),
+    FUNCTION 1 btcharcmp(\"char\", \"char\");
+
+    -- Operator classes for date and time types
+    CREATE OPERATOR FAMILY pgfdb_timestamp_ops USING pgfdb;
+
+    CREATE OPERATOR CLASS pgfdb_timestamp
+    DEFAULT FOR TYPE TIMESTAMP USING pgfdb
+    FAMILY pgfdb_timestamp_ops AS
+    OPERATOR 1 < (TIMESTAMP, TIMESTAMP),
+    OPERATOR 2 <= (TIMESTAMP, TIMESTAMP),
+    OPERATOR 3 = (TIMESTAMP, TIMESTAMP),
+    OPERATOR 4 >= (TIMESTAMP, TIMESTAMP),
+    OPERATOR 5 > (TIMESTAMP, TIMESTAMP),
+    OPERATOR 6 != (TIMESTAMP, TIMESTAMP),
+    FUNCTION 1 timestamp_cmp(TIMESTAMP, TIMESTAMP);
+
+    CREATE OPERATOR FAMILY pgfdb_timestamptz_ops USING pgfdb;
+
+    CREATE OPERATOR CLASS pgfdb_timestamptz
+    DEFAULT FOR TYPE TIMESTAMPTZ USING pgfdb
+    FAMILY pgfdb_timestamptz_ops AS
+    OPERATOR 1 < (TIMESTAMPTZ, TIMESTAMPTZ),
+    OPERATOR 2 <= (TIMESTAMPTZ, TIMESTAMPTZ),
+    OPERATOR 3 = (TIMESTAMPTZ, TIMESTAMPTZ),
+    OPERATOR 4 >= (TIMESTAMPTZ, TIMESTAMPTZ),
+    OPERATOR 5 > (TIMESTAMPTZ, TIMESTAMPTZ),
+    OPERATOR 6 != (TIMESTAMPTZ, TIMESTAMPTZ),
+    FUNCTION 1 timestamptz_cmp(TIMESTAMPTZ, TIMESTAMPTZ);
+
+    CREATE OPERATOR FAMILY pgfdb_date_ops USING pgfdb;
+
+    CREATE OPERATOR CLASS pgfdb_date
+    DEFAULT FOR TYPE DATE USING pgfdb
+    FAMILY pgfdb_date_ops AS
+    OPERATOR 1 < (DATE, DATE),
+    OPERATOR 2 <= (DATE, DATE),
+    OPERATOR 3 = (DATE, DATE),
+    OPERATOR 4 >= (DATE, DATE),
+    OPERATOR 5 > (DATE, DATE),
+    OPERATOR 6 != (DATE, DATE),
+    FUNCTION 1 date_cmp(DATE, DATE);
+
+    CREATE OPERATOR FAMILY pgfdb_time_ops USING pgfdb;
+
+    CREATE OPERATOR CLASS pgfdb_time
+    DEFAULT FOR TYPE TIME USING pgfdb
+    FAMILY pgfdb_time_ops AS
+    OPERATOR 1 < (TIME, TIME),
+    OPERATOR 2 <= (TIME, TIME),
+    OPERATOR 3 = (TIME, TIME),
+    OPERATOR 4 >= (TIME, TIME),
+    OPERATOR 5 > (TIME, TIME),
+    OPERATOR 6 != (TIME, TIME),
+    FUNCTION 1 time_cmp(TIME, TIME);
+
+    CREATE OPERATOR FAMILY pgfdb_interval_ops USING pgfdb;
+
+    CREATE OPERATOR CLASS pgfdb_interval
+    DEFAULT FOR TYPE INTERVAL USING pgfdb
+    FAMILY pgfdb_interval_ops AS
+    OPERATOR 1 < (INTERVAL, INTERVAL),
+    OPERATOR 2 <= (INTERVAL, INTERVAL),
+    OPERATOR 3 = (INTERVAL, INTERVAL),
+    OPERATOR 4 >= (INTERVAL, INTERVAL),
+    OPERATOR 5 > (INTERVAL, INTERVAL),
+    OPERATOR 6 != (INTERVAL, INTERVAL),
+    FUNCTION 1 interval_cmp(INTERVAL, INTERVAL);
+
+    -- Operator class for blank-padded character type
+    CREATE OPERATOR FAMILY pgfdb_bpchar_ops USING pgfdb;
+
+    CREATE OPERATOR CLASS pgfdb_bpchar
+    DEFAULT FOR TYPE CHARACTER USING pgfdb
+    FAMILY pgfdb_bpchar_ops AS
+    OPERATOR 1 < (CHARACTER, CHARACTER),
+    OPERATOR 2 <= (CHARACTER, CHARACTER),
+    OPERATOR 3 = (CHARACTER, CHARACTER),
+    OPERATOR 4 >= (CHARACTER, CHARACTER),
+    OPERATOR 5 > (CHARACTER, CHARACTER),
+    OPERATOR 6 != (CHARACTER, CHARACTER),
+    FUNCTION 1 bpcharcmp(CHARACTER, CHARACTER);
+
+    -- Operator class for all enum types, which are compared by their sort order
+    CREATE OPERATOR FAMILY pgfdb_enum_ops USING pgfdb;
+
+    CREATE OPERATOR CLASS pgfdb_enum
+    DEFAULT FOR TYPE ANYENUM USING pgfdb
+    FAMILY pgfdb_enum_ops AS
+    OPERATOR 1 < (ANYENUM, ANYENUM),
+    OPERATOR 2 <= (ANYENUM, ANYENUM),
+    OPERATOR 3 = (ANYENUM, ANYENUM),
+    OPERATOR 4 >= (ANYENUM, ANYENUM),
+    OPERATOR 5 > (ANYENUM, ANYENUM),
+    OPERATOR 6 != (ANYENUM, ANYENUM),
+    FUNCTION 1 enum_cmp(ANYENUM, ANYENUM);
+
+    -- Operator class for all array types, which are compared element by element
+    CREATE OPERATOR FAMILY pgfdb_array_ops USING pgfdb;
+
+    CREATE OPERATOR CLASS pgfdb_array
+    DEFAULT FOR TYPE ANYARRAY USING pgfdb
+    FAMILY pgfdb_array_ops AS
+    OPERATOR 1 < (ANYARRAY, ANYARRAY),
+    OPERATOR 2 <= (ANYARRAY, ANYARRAY),
+    OPERATOR 3 = (ANYARRAY, ANYARRAY),
+    OPERATOR 4 >= (ANYARRAY, ANYARRAY),
+    OPERATOR 5 > (ANYARRAY, ANYARRAY),
+    OPERATOR 6 != (ANYARRAY, ANYARRAY),
+    FUNCTION 1 btarraycmp(ANYARRAY, ANYARRAY);
+
+    -- Operator class for composite types, which are compared field by field
+    CREATE OPERATOR FAMILY pgfdb_record_ops USING pgfdb;
+
+    CREATE OPERATOR CLASS pgfdb_record
+    DEFAULT FOR TYPE RECORD USING pgfdb
+    FAMILY pgfdb_record_ops AS
+    OPERATOR 1 < (RECORD, RECORD),
+    OPERATOR 2 <= (RECORD, RECORD),
+    OPERATOR 3 = (RECORD, RECORD),
+    OPERATOR 4 >= (RECORD, RECORD),
+    OPERATOR 5 > (RECORD, RECORD),
+    OPERATOR 6 != (RECORD, RECORD),
+    FUNCTION 1 btrecordcmp(RECORD, RECORD);
+
     -- Add cross-type operators to integer family
     ALTER OPERATOR FAMILY pgfdb_integer_ops USING pgfdb ADD
         -- INTEGER to BIGINT comparisons
@@ -102,6 +301,7 @@ use std::ptr;
         OPERATOR 4 >= (INTEGER, BIGINT),
         OPERATOR 5 > (INTEGER, BIGINT),
         OPERATOR 6 != (INTEGER, BIGINT),
+        FUNCTION 1 (INTEGER, BIGINT) btint48cmp(INTEGER, BIGINT),
 
         -- BIGINT to INTEGER comparisons
         OPERATOR 1 < (BIGINT, INTEGER),
@@ -110,6 +310,7 @@ use std::ptr;
         OPERATOR 4 >= (BIGINT, INTEGER),
         OPERATOR 5 > (BIGINT, INTEGER),
         OPERATOR 6 != (BIGINT, INTEGER),
+        FUNCTION 1 (BIGINT, INTEGER) btint84cmp(BIGINT, INTEGER),
 
         -- INTEGER to SMALLINT comparisons
         OPERATOR 1 < (INTEGER, SMALLINT),
@@ -118,6 +319,7 @@ use std::ptr;
         OPERATOR 4 >= (INTEGER, SMALLINT),
         OPERATOR 5 > (INTEGER, SMALLINT),
         OPERATOR 6 != (INTEGER, SMALLINT),
+        FUNCTION 1 (INTEGER, SMALLINT) btint42cmp(INTEGER, SMALLINT),
 
         -- SMALLINT to INTEGER comparisons
         OPERATOR 1 < (SMALLINT, INTEGER),
@@ -126,6 +328,7 @@ use std::ptr;
         OPERATOR 4 >= (SMALLINT, INTEGER),
         OPERATOR 5 > (SMALLINT, INTEGER),
         OPERATOR 6 != (SMALLINT, INTEGER),
+        FUNCTION 1 (SMALLINT, INTEGER) btint24cmp(SMALLINT, INTEGER),
 
         -- BIGINT to SMALLINT comparisons
         OPERATOR 1 < (BIGINT, SMALLINT),
@@ -134,6 +337,7 @@ use std::ptr;
         OPERATOR 4 >= (BIGINT, SMALLINT),
         OPERATOR 5 > (BIGINT, SMALLINT),
         OPERATOR 6 != (BIGINT, SMALLINT),
+        FUNCTION 1 (BIGINT, SMALLINT) btint82cmp(BIGINT, SMALLINT),
 
         -- SMALLINT to BIGINT comparisons
         OPERATOR 1 < (SMALLINT, BIGINT),
@@ -141,7 +345,8 @@ use std::ptr;
         OPERATOR 3 = (SMALLINT, BIGINT),
         OPERATOR 4 >= (SMALLINT, BIGINT),
         OPERATOR 5 > (SMALLINT, BIGINT),
-        OPERATOR 6 != (SMALLINT, BIGINT);
+        OPERATOR 6 != (SMALLINT, BIGINT),
+        FUNCTION 1 (SMALLINT, BIGINT) btint28cmp(SMALLINT, BIGINT);
 
     -- Add cross-type operators to float family
     ALTER OPERATOR FAMILY pgfdb_float_ops USING pgfdb ADD
@@ -152,6 +357,7 @@ use std::ptr;
         OPERATOR 4 >= (REAL, DOUBLE PRECISION),
         OPERATOR 5 > (REAL, DOUBLE PRECISION),
         OPERATOR 6 != (REAL, DOUBLE PRECISION),
+        FUNCTION 1 (REAL, DOUBLE PRECISION) btfloat48cmp(REAL, DOUBLE PRECISION),
 
         -- DOUBLE PRECISION to REAL comparisons
         OPERATOR 1 < (DOUBLE PRECISION, REAL),
@@ -159,7 +365,8 @@ use std::ptr;
         OPERATOR 3 = (DOUBLE PRECISION, REAL),
         OPERATOR 4 >= (DOUBLE PRECISION, REAL),
         OPERATOR 5 > (DOUBLE PRECISION, REAL),
-        OPERATOR 6 != (DOUBLE PRECISION, REAL);
+        OPERATOR 6 != (DOUBLE PRECISION, REAL),
+        FUNCTION 1 (DOUBLE PRECISION, REAL) btfloat84cmp(DOUBLE PRECISION, REAL);
     ")]
 pub fn pgfdb_iam_handler() -> IndexAmHandler {
     IndexAmHandler
@@ -189,51 +396,59 @@ unsafe impl BoxRet for IndexAmHandler {
             index_am_routine.ambuildempty = Some(build::ambuildempty);
             index_am_routine.aminsert = Some(build::aminsert);
             index_am_routine.aminsertcleanup = None; // Not needed
-            index_am_routine.ambulkdelete = None; // Optional - for bulk deletes
-            index_am_routine.amvacuumcleanup = None; // Optional - for VACUUM
+            index_am_routine.ambulkdelete = Some(vacuum::ambulkdelete);
+            index_am_routine.amvacuumcleanup = Some(vacuum::amvacuumcleanup);
             index_am_routine.amcanreturn = None; // Optional - index-only scans
             index_am_routine.amcostestimate = Some(scan::amcostestimate); // Optional - custom cost estimation
             index_am_routine.amoptions = Some(amoptions);
             index_am_routine.amproperty = None; // Optional - index properties
-            index_am_routine.ambuildphasename = None; // Optional - progress reporting
-            index_am_routine.amvalidate = None; // Optional - index validation
+            index_am_routine.ambuildphasename = Some(build::ambuildphasename);
+            index_am_routine.amvalidate = Some(validate::amvalidate);
             index_am_routine.amadjustmembers = None; // Optional - parallel scan
             index_am_routine.ambeginscan = Some(scan::ambeginscan);
             index_am_routine.amrescan = Some(scan::amrescan);
             index_am_routine.amgettuple = Some(scan::amgettuple);
             index_am_routine.amendscan = Some(scan::amendscan);
-            index_am_routine.ammarkpos = None; // Optional - mark/restore position
-            index_am_routine.amrestrpos = None; // Optional - mark/restore position
+            index_am_routine.ammarkpos = Some(scan::ammarkpos);
+            index_am_routine.amrestrpos = Some(scan::amrestrpos);
 
             // Bitmap scans not supported
             index_am_routine.amgetbitmap = None;
 
-            // Parallel scans not supported
-            index_am_routine.amestimateparallelscan = None;
-            index_am_routine.aminitparallelscan = None;
-            index_am_routine.amparallelrescan = None;
+            // Parallel scans split the index ranges into chunks at FDB split points, which workers claim one by one
+            index_am_routine.amestimateparallelscan = Some(scan::amestimateparallelscan);
+            index_am_routine.aminitparallelscan = Some(scan::aminitparallelscan);
+            index_am_routine.amparallelrescan = Some(scan::amparallelrescan);
 
             // Strategies:
+            // 1: <
+            // 2: <=
             // 3: =
-            // 4: <=
-            // 5: <
+            // 4: >=
+            // 5: >
+            // 6: !=
             index_am_routine.amstrategies = 6;
 
-            index_am_routine.amsupport = 0;
+            // Support functions:
+            // 1: btree comparison function for the type, matching the order of our encoded index keys.
+            // The planner doesn't call it: it links our index order to the btree sort order of the type by
+            // looking up our `<` operator in a btree operator family, which is what lets the index satisfy
+            // ORDER BY and merge joins. Text is only ordered once its keys are built from sort keys.
+            index_am_routine.amsupport = 1;
             index_am_routine.amoptsprocnum = 0;
             index_am_routine.amcanorder = true;
             index_am_routine.amcanorderbyop = false;
             index_am_routine.amcanbackward = true;
             index_am_routine.amcanunique = true;
             index_am_routine.amcanmulticol = true;
-            index_am_routine.amoptionalkey = false;
-            index_am_routine.amsearcharray = false;
+            index_am_routine.amoptionalkey = true;
+            index_am_routine.amsearcharray = true;
             index_am_routine.amsearchnulls = true;
             index_am_routine.amstorage = false;
             index_am_routine.amclusterable = false;
             index_am_routine.ampredlocks = false;
-            index_am_routine.amcanparallel = false;
-            index_am_routine.amcanbuildparallel = false;
+            index_am_routine.amcanparallel = true;
+            index_am_routine.amcanbuildparallel = true;
             index_am_routine.amcaninclude = false;
             index_am_routine.amusemaintenanceworkmem = false;
             index_am_routine.amsummarizing = false;
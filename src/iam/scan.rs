@@ -1,37 +1,155 @@
+use core::ffi::c_void;
 use core::slice;
+use std::sync::atomic::{AtomicU32, Ordering};
 
-use foundationdb::future::{FdbSlice, FdbValues};
-use foundationdb::tuple::{Element, Subspace};
-use foundationdb::{FdbResult, RangeOption, tuple::unpack};
+use foundationdb::future::FdbValues;
+use foundationdb::tuple::{Element, Subspace, pack, unpack};
+use foundationdb::{FdbResult, RangeOption};
 use foundationdb::{KeySelector, Transaction};
-use futures::future::join_all;
+use futures::future::try_join_all;
 use futures::stream::empty;
-use futures::{FutureExt, StreamExt, stream::BoxStream};
-use futures::{Stream, TryFutureExt, TryStreamExt, stream};
+use futures::{Stream, StreamExt, TryStreamExt, stream, stream::BoxStream};
 use pg_sys::{
-    Cost, IndexPath, IndexScanDesc, IndexScanDescData, JoinType::JOIN_INNER, PlannerInfo, Relation,
-    ScanDirection, ScanKey, Selectivity, clauselist_selectivity, get_quals_from_indexclauses,
+    AttStatsSlot, ConditionVariable, Cost, IndexClause, IndexOptInfo, IndexPath, IndexScanDesc,
+    IndexScanDescData, JoinType::JOIN_INNER, Node, NodeTag, PlannerInfo, Relation, RestrictInfo,
+    ScalarArrayOpExpr, ScanDirection, ScanKey, Selectivity, VariableStatData,
+    add_predicate_to_index_quals, clauselist_selectivity, cpu_index_tuple_cost, cpu_operator_cost,
+    estimate_array_length, get_quals_from_indexclauses, random_page_cost,
 };
+use pgrx::guc::{GucContext, GucFlags, GucRegistry, GucSetting};
 use pgrx::itemptr::item_pointer_set_all;
+use pgrx::list::List;
+use pgrx::memcx::current_context;
 use pgrx::pg_sys::panic::ErrorReportable;
-use pgrx::pg_sys::{FormData_pg_attribute, SK_SEARCHNOTNULL, SK_SEARCHNULL, ScanKeyData};
+use pgrx::pg_sys::{
+    FormData_pg_attribute, InvalidOid, Oid, SK_ISNULL, SK_SEARCHARRAY, SK_SEARCHNOTNULL,
+    SK_SEARCHNULL, ScanKeyData,
+};
 use pgrx::prelude::*;
 use pollster::FutureExt as _;
 
 use crate::coding::Tuple;
 use crate::errors::FdbErrorExt;
-use crate::iam::utils::encode_datum_for_index;
+use crate::iam::utils::{ColumnOrder, deconstruct_array_datum, encode_datum_for_index};
+use crate::transaction::{Cut, chunk_ranges, plan_chunks, unpack_cuts};
 use crate::tuple_cache;
 
 #[repr(C)]
 struct FdbIndexScan {
     // Must be first field to ensure proper casting
     base: IndexScanDescData,
-    // Stream of values from FDB
-    values: BoxStream<'static, FdbResult<(u32, FdbSlice)>>,
+    // What parts of the index to scan, built from the scan keys in `amrescan`
+    plan: ScanPlan,
+    // Stream of values from FDB. This is created lazily on the first `amgettuple` as that's when we know the direction.
+    values: Option<BoxStream<'static, FdbResult<IndexEntry>>>,
+    // Direction of the current stream
+    direction: ScanDirection::Type,
+    // The last entry returned from the scan, used to pick up where we left off if the direction changes
+    position: Option<ScanPosition>,
+    // Set once the current stream has run out of entries
+    exhausted: bool,
+    // Position saved by `ammarkpos`, to be restored by `amrestrpos`
+    mark: Option<ScanPosition>,
+}
+
+// Position of an entry in a scan, identified by its index key and which of the scan ranges it was read from
+#[derive(Clone)]
+struct ScanPosition {
+    range: usize,
+    key: Vec<u8>,
+}
+
+// How to scan the index for a set of scan keys
+enum ScanPlan {
+    // Ranges of the index to scan in index order
+    Ranges(Vec<RangeOption<'static>>),
+    // Skip scan for when some leading columns of the index don't have any conditions
+    Skip(SkipScan),
+}
+
+// A skip scan walks the distinct values of the leading columns which don't have any conditions and, for each of
+// them, scans the ranges for the conditions on the trailing columns. This is efficient when the skipped columns
+// have few distinct values, like a tenant ID in an index over `(tenant_id, email)`.
+#[derive(Clone)]
+struct SkipScan {
+    // Subspace of the leading columns with equality conditions, if any, before the skipped columns
+    prefix: Subspace,
+    // Number of columns without conditions to skip over
+    skipped_columns: usize,
+    // Conditions on each of the columns after the skipped ones
+    conditions: Vec<Vec<ScanCondition>>,
+}
+
+// An entry read from the index together with the table row it points to
+pub(super) struct IndexEntry {
+    pub(super) range: usize,
+    key: Vec<u8>,
+    id: u32,
+    pub(super) row: Vec<u8>,
+}
+
+// Where the table rows for the entries of an index are looked up. The row ID is the last element of the index keys,
+// after the elements of the index subspace and one for each index column.
+#[derive(Clone, Copy)]
+pub(super) struct RowLookup {
+    table_oid: Oid,
+    id_element: usize,
+}
+
+impl RowLookup {
+    pub(super) unsafe fn new(table_oid: Oid, index_relation: Relation) -> Self {
+        unsafe {
+            let index_subspace = crate::subspace::index((*index_relation).rd_locator.relNumber);
+            let natts = (*(*index_relation).rd_att).natts as usize;
+            RowLookup {
+                table_oid,
+                id_element: subspace_elements(&index_subspace).len() + natts,
+            }
+        }
+    }
+
+    // Mapper for mapped range reads, which reads the table row with the ID of each index key. The row key is the
+    // table subspace followed by the ID, like for `subspace::table(oid).pack(&id)`.
+    fn mapper(&self) -> Vec<u8> {
+        let mut elements = subspace_elements(&crate::subspace::table(self.table_oid));
+        elements.push(Element::String(
+            format!("{{K[{}]}}", self.id_element).into(),
+        ));
+        elements.push(Element::String("{...}".into()));
+        pack(&elements)
+    }
 }
 
+// The tuple elements that make up the prefix of a subspace
+fn subspace_elements(subspace: &Subspace) -> Vec<Element<'static>> {
+    let elements: Vec<Element> = unpack(subspace.bytes()).unwrap_or_report();
+    elements.into_iter().map(Element::into_owned).collect()
+}
+
+static MAPPED_RANGES: GucSetting<bool> = GucSetting::<bool>::new(true);
+
+pub fn init() {
+    GucRegistry::define_bool_guc(
+        c"pgfdb.mapped_ranges",
+        c"Read index entries and their table rows with FDB mapped ranges.",
+        c"Mapped ranges read the table rows for a batch of index entries in the same request as the entries, rather \
+        than in a second round trip. They are only used when the transaction hasn't written anything.",
+        &MAPPED_RANGES,
+        GucContext::Userset,
+        GucFlags::default(),
+    );
+}
+
+// Number of index entries we expect to be returned per batch of a range read. The table rows for a batch are read
+// concurrently, so looking them up costs a round trip per batch rather than per row.
+const ROWS_PER_BATCH: f64 = 100.0;
+
 // https://www.postgresql.org/docs/current/index-cost-estimation.html
+//
+// The cost of our index scans is dominated by round trips to FDB rather than by reading pages, so we charge every
+// request we expect to make like a random page read. We read each range of the scan plan, look up the table rows
+// for each batch of index entries (unless the scan is index-only), and pay CPU costs for each entry like the
+// built-in index types.
 pub unsafe extern "C-unwind" fn amcostestimate(
     root: *mut PlannerInfo,
     path: *mut IndexPath,
@@ -45,22 +163,194 @@ pub unsafe extern "C-unwind" fn amcostestimate(
     unsafe {
         log!("IAM: Calculate cost estimate");
 
-        *index_startup_cost = 0.0;
-
-        *index_total_cost = 0.0;
-
-        *index_correlation = 0.0;
-
-        *index_pages = 0.0;
+        let index_info = (*path).indexinfo;
+        let baserel = (*index_info).rel;
 
+        // Partial indexes only contain the rows matching their predicate, so it's included in the selectivity
         let index_quals = get_quals_from_indexclauses((*path).indexclauses);
-        *index_selectivity = clauselist_selectivity(
+        let selectivity_quals = add_predicate_to_index_quals(index_info, index_quals);
+        let selectivity = clauselist_selectivity(
             root,
-            index_quals,
-            (*(*(*path).indexinfo).rel).relid as i32,
+            selectivity_quals,
+            (*baserel).relid as i32,
             JOIN_INNER,
             std::ptr::null_mut(),
         );
+        let num_rows = (selectivity * (*baserel).tuples).round().max(1.0);
+
+        let leading_column = LeadingColumnStats::examine(root, index_info);
+        let num_ranges = estimate_num_ranges(root, path, leading_column.num_distinct);
+        let num_batches = (num_rows / ROWS_PER_BATCH).ceil();
+        let index_only = (*path).path.pathtype == NodeTag::T_IndexOnlyScan;
+
+        let round_trip_cost = random_page_cost;
+        let range_cost = num_ranges * round_trip_cost;
+        let table_cost = if index_only {
+            0.0
+        } else {
+            num_batches * round_trip_cost
+        };
+        let num_quals = if index_quals.is_null() {
+            0
+        } else {
+            (*index_quals).length
+        };
+        let cpu_cost = num_rows * (cpu_index_tuple_cost + cpu_operator_cost * num_quals as f64);
+
+        // Before returning the first row, we need to read the first range and look up its table rows
+        *index_startup_cost = if index_only {
+            round_trip_cost
+        } else {
+            2.0 * round_trip_cost
+        };
+        *index_total_cost = range_cost + table_cost + cpu_cost;
+        *index_selectivity = selectivity;
+
+        // Postgres adds the cost of fetching table pages on top of ours, assuming random page reads unless the
+        // index order is correlated with the table. Like btree, we use the correlation statistic of the leading
+        // column. Without statistics, we report full correlation, as our table rows are point reads which we have
+        // already paid for, which keeps that extra cost to the fraction of the table we actually read.
+        *index_correlation = leading_column.correlation.unwrap_or(1.0);
+
+        // We don't have pages, but the number of requests to FDB is the closest equivalent
+        *index_pages = num_ranges + num_batches;
+    }
+}
+
+// Estimates the number of ranges a scan reads. Each `= ANY(array)` condition multiplies the number of ranges by the
+// length of its array. Without a condition on the leading column, we do a skip scan which reads the ranges for
+// every distinct value of that column.
+unsafe fn estimate_num_ranges(
+    root: *mut PlannerInfo,
+    path: *mut IndexPath,
+    leading_distinct: f64,
+) -> f64 {
+    unsafe {
+        current_context(|ctx| {
+            let clauses: List<*mut c_void> =
+                List::downcast_ptr_in_memcx((*path).indexclauses, ctx).unwrap();
+            if clauses.is_empty() {
+                return 1.0;
+            }
+
+            let mut num_ranges = 1.0;
+            let mut leading_column_constrained = false;
+
+            for clause in clauses.iter() {
+                let clause = *clause as *mut IndexClause;
+                leading_column_constrained |= (*clause).indexcol == 0;
+
+                let rinfos: List<*mut c_void> =
+                    List::downcast_ptr_in_memcx((*clause).indexquals, ctx).unwrap();
+                for rinfo in rinfos.iter() {
+                    let expr = (*(*rinfo as *mut RestrictInfo)).clause as *mut Node;
+                    if !pgrx::is_a(expr, NodeTag::T_ScalarArrayOpExpr) {
+                        continue;
+                    }
+
+                    let args: List<*mut c_void> =
+                        List::downcast_ptr_in_memcx((*(expr as *mut ScalarArrayOpExpr)).args, ctx)
+                            .unwrap();
+                    if let Some(array) = args.get(1) {
+                        num_ranges *= estimate_array_length(root, *array as *mut Node).max(1.0);
+                    }
+                }
+            }
+
+            if !leading_column_constrained {
+                num_ranges *= leading_distinct.max(1.0);
+            }
+
+            num_ranges
+        })
+    }
+}
+
+// Statistics of the leading column of an index, looked up like btree does for its cost estimates
+struct LeadingColumnStats {
+    // Estimated number of distinct values. Without statistics, Postgres guesses 200, or the number of rows for
+    // columns with a unique index.
+    num_distinct: f64,
+    // Correlation between the order of the column and the order of the table, if there are statistics for it
+    correlation: Option<f64>,
+}
+
+impl LeadingColumnStats {
+    unsafe fn examine(root: *mut PlannerInfo, index_info: *mut IndexOptInfo) -> Self {
+        unsafe {
+            let mut vardata = VariableStatData::default();
+            pg_sys::examine_variable(root, leading_column_expr(root, index_info), 0, &mut vardata);
+
+            let mut is_default = false;
+            let num_distinct = pg_sys::get_variable_numdistinct(&mut vardata, &mut is_default);
+
+            let mut correlation = None;
+            if !vardata.statsTuple.is_null() {
+                // Statistics are ordered by the `<` operator of the column type, which is also ours
+                let sort_operator = pg_sys::get_opfamily_member(
+                    *(*index_info).opfamily,
+                    *(*index_info).opcintype,
+                    *(*index_info).opcintype,
+                    pg_sys::BTLessStrategyNumber as i16,
+                );
+                let mut slot = AttStatsSlot::default();
+                if sort_operator != InvalidOid
+                    && pg_sys::get_attstatsslot(
+                        &mut slot,
+                        vardata.statsTuple,
+                        pg_sys::STATISTIC_KIND_CORRELATION as i32,
+                        sort_operator,
+                        pg_sys::ATTSTATSSLOT_NUMBERS as i32,
+                    )
+                {
+                    let mut value = *slot.numbers as f64;
+                    if *(*index_info).reverse_sort {
+                        value = -value;
+                    }
+                    // Later columns weaken the correlation of the index as a whole
+                    if (*index_info).nkeycolumns > 1 {
+                        value *= 0.75;
+                    }
+                    correlation = Some(value);
+                    pg_sys::free_attstatsslot(&mut slot);
+                }
+
+                // Like the `ReleaseVariableStats` macro
+                if let Some(free) = vardata.freefunc {
+                    free(vardata.statsTuple);
+                }
+            }
+
+            LeadingColumnStats {
+                num_distinct,
+                correlation,
+            }
+        }
+    }
+}
+
+// Returns the leading column of an index as an expression over the table, which is either a column of the table or
+// the first index expression
+unsafe fn leading_column_expr(root: *mut PlannerInfo, index_info: *mut IndexOptInfo) -> *mut Node {
+    unsafe {
+        let relid = (*(*index_info).rel).relid;
+        let attno = *(*index_info).indexkeys as i16;
+        if attno == 0 {
+            return pg_sys::list_nth((*index_info).indexprs, 0) as *mut Node;
+        }
+
+        let table_oid = (**(*root).simple_rte_array.add(relid as usize)).relid;
+        let mut type_oid = InvalidOid;
+        let mut type_mod = -1;
+        let mut collation = InvalidOid;
+        pg_sys::get_atttypetypmodcoll(
+            table_oid,
+            attno,
+            &mut type_oid,
+            &mut type_mod,
+            &mut collation,
+        );
+        pg_sys::makeVar(relid as i32, attno, type_oid, type_mod, collation, 0) as *mut Node
     }
 }
 
@@ -84,13 +374,16 @@ pub unsafe extern "C-unwind" fn ambeginscan(
         scan.base.xs_snapshot = std::ptr::null_mut();
         scan.base.xs_want_itup = false;
         scan.base.xs_temp_snap = false;
+        scan.direction = ScanDirection::ForwardScanDirection;
+        scan.exhausted = false;
 
-        // Create an empty stream initially - will be populated in rescan
-        let empty_stream = futures::stream::empty().boxed();
-
-        // We must use ptr::write to avoid dropping uninitialized memory
+        // Start out without any ranges or stream, these will be populated in rescan.
+        // We must use ptr::write to avoid dropping uninitialized memory.
         let scan_pointer = scan.as_ptr();
-        std::ptr::write(&mut (*scan_pointer).values, empty_stream);
+        std::ptr::write(&mut (*scan_pointer).plan, ScanPlan::Ranges(Vec::new()));
+        std::ptr::write(&mut (*scan_pointer).values, None);
+        std::ptr::write(&mut (*scan_pointer).position, None);
+        std::ptr::write(&mut (*scan_pointer).mark, None);
 
         scan.into_pg() as IndexScanDesc
     }
@@ -104,44 +397,76 @@ pub unsafe extern "C-unwind" fn amgettuple(
 ) -> bool {
     log!("IAM: Get tuple, direction={}", direction);
 
-    // Only support forward scans for now
-    if direction != ScanDirection::ForwardScanDirection {
-        log!("IAM: Only forward scans are supported");
+    if direction == ScanDirection::NoMovementScanDirection {
         return false;
     }
 
-    let fdb_scan = scan as *mut FdbIndexScan;
+    let fdb_scan = unsafe { &mut *(scan as *mut FdbIndexScan) };
+
+    // Start a new stream if this is the first fetch or if the direction has changed, for example when
+    // fetching backwards from a scrollable cursor. When changing direction, we continue from the last
+    // returned entry. If the previous stream had run past its end, the last returned entry is also the
+    // next one in the new direction, so it must be included.
+    if fdb_scan.values.is_none() || fdb_scan.direction != direction {
+        let lookup = unsafe {
+            RowLookup::new(
+                (*fdb_scan.base.heapRelation).rd_id,
+                fdb_scan.base.indexRelation,
+            )
+        };
+        let stream = if fdb_scan.base.parallel_scan.is_null() {
+            create_stream(
+                lookup,
+                &fdb_scan.plan,
+                direction,
+                fdb_scan.position.as_ref(),
+                fdb_scan.exhausted,
+            )
+        } else {
+            // Parallel scans are never scrollable, so the direction doesn't change during the scan
+            unsafe { create_parallel_stream(lookup, &fdb_scan.plan, direction, scan) }
+        };
+
+        fdb_scan.values = Some(stream);
+        fdb_scan.direction = direction;
+        fdb_scan.exhausted = false;
+    } else if fdb_scan.exhausted {
+        return false;
+    }
 
     // Get the next key-value pair from the stream
-    let next = unsafe { (*fdb_scan).values.next() }.block_on();
+    let next = fdb_scan.values.as_mut().unwrap().next().block_on();
 
     // If there's no more data, return false
     let Some(result) = next else {
+        fdb_scan.exhausted = true;
         return false;
     };
 
-    let (id, value) = result.unwrap_or_pg_error();
+    let entry = result.unwrap_or_pg_error();
+    fdb_scan.position = Some(ScanPosition {
+        range: entry.range,
+        key: entry.key,
+    });
 
     // Our index scan doesn't just fetch the index row, it also fetches the corresponding table row.
     // This is to avoid the TAM having to look up each table row one by one, which gets very slow for large
     // index scans. Here we store the fetched table row in the tuple cache so that the TAM can use it in `index_fetch_tuple`.
-    let tuple = Tuple::deserialize(&value);
+    let tuple = Tuple::deserialize(&entry.row);
     tuple_cache::populate(tuple);
 
     // Use a fixed offset of 1 (first item in the block)
     let offset_num = 1u16;
 
-    unsafe {
-        // Store back the ID to be looked up by the table access method
-        item_pointer_set_all(&mut (*fdb_scan).base.xs_heaptid, id, offset_num);
+    // Store back the ID to be looked up by the table access method
+    item_pointer_set_all(&mut fdb_scan.base.xs_heaptid, entry.id, offset_num);
 
-        // Recheck is probbaly not necessary but the NULL handling right now probably requires it
-        (*fdb_scan).base.xs_recheck = true;
-        (*fdb_scan).base.xs_recheckorderby = true;
+    // Recheck is probbaly not necessary but the NULL handling right now probably requires it
+    fdb_scan.base.xs_recheck = true;
+    fdb_scan.base.xs_recheckorderby = true;
 
-        // This we might be able to check more effectively
-        (*fdb_scan).base.xs_heap_continue = true;
-    }
+    // This we might be able to check more effectively
+    fdb_scan.base.xs_heap_continue = true;
 
     true
 }
@@ -166,193 +491,985 @@ pub unsafe extern "C-unwind" fn amrescan(
 
         let index_relation = (*scan).indexRelation;
         let index_tuple_desc = (*index_relation).rd_att;
-        let attrs = (*index_tuple_desc)
-            .attrs
-            .as_slice((*index_tuple_desc).natts as usize);
-        let scan_keys = slice::from_raw_parts(keys, nkeys as usize);
+        let natts = (*index_tuple_desc).natts as usize;
+        let attrs = (*index_tuple_desc).attrs.as_slice(natts);
+        let options = slice::from_raw_parts((*index_relation).rd_indoption, natts);
+        let scan_keys = if nkeys > 0 {
+            slice::from_raw_parts(keys, nkeys as usize)
+        } else {
+            // Scans without any keys, for example to satisfy an ORDER BY, can pass a null pointer
+            &[]
+        };
+
+        // Construct a plan for what parts of the index we need to iterate over based on the scan keys
+        let index_subspace = crate::subspace::index((*index_relation).rd_locator.relNumber);
+        let plan = scan_plan(index_subspace, scan_keys, attrs, options);
+
+        // Reset the scan, the stream will be created on the next call to `amgettuple`
+        (*fdb_scan).plan = plan;
+        (*fdb_scan).values = None;
+        (*fdb_scan).position = None;
+        (*fdb_scan).exhausted = false;
+        (*fdb_scan).mark = None;
+    }
+}
+
+// Mark the position of the entry last returned by the scan, which merge joins use to go back to the start of a
+// group of equal keys on their inner side
+pub unsafe extern "C-unwind" fn ammarkpos(scan: IndexScanDesc) {
+    log!("IAM: Mark position");
+
+    let fdb_scan = unsafe { &mut *(scan as *mut FdbIndexScan) };
+    fdb_scan.mark = fdb_scan.position.clone();
+}
+
+// Restore the marked position, so that the next entry returned is the one after it. The stream is restarted right
+// after the marked key, or from the start of the scan if nothing had been returned when the position was marked.
+pub unsafe extern "C-unwind" fn amrestrpos(scan: IndexScanDesc) {
+    log!("IAM: Restore position");
+
+    let fdb_scan = unsafe { &mut *(scan as *mut FdbIndexScan) };
+    fdb_scan.position = fdb_scan.mark.clone();
+    fdb_scan.values = None;
+    fdb_scan.exhausted = false;
+}
+
+// Maximum number of index ranges to read from concurrently
+pub(super) const CONCURRENT_RANGE_READS: usize = 16;
+
+// Create a stream of index entries from FDB for the given scan plan, iterating in the given direction.
+// If a position is passed, the stream picks up from there rather than from the start of the scan. The entry at
+// the position itself is only included if `inclusive` is set.
+fn create_stream(
+    lookup: RowLookup,
+    plan: &ScanPlan,
+    direction: ScanDirection::Type,
+    position: Option<&ScanPosition>,
+    inclusive: bool,
+) -> BoxStream<'static, FdbResult<IndexEntry>> {
+    let backward = direction == ScanDirection::BackwardScanDirection;
+
+    match plan {
+        ScanPlan::Ranges(ranges) => read_ranges(lookup, ranges, backward, position, inclusive),
+        ScanPlan::Skip(skip_scan) => skip_scan.read(lookup, backward, position, inclusive),
+    }
+}
+
+// Create a stream of index entries for the given ranges chained together. The position refers to one of these
+// ranges, see `create_stream`.
+pub(super) fn read_ranges(
+    lookup: RowLookup,
+    ranges: &[RangeOption<'static>],
+    backward: bool,
+    position: Option<&ScanPosition>,
+    inclusive: bool,
+) -> BoxStream<'static, FdbResult<IndexEntry>> {
+    let txn = crate::transaction::get_transaction();
+
+    let mut ranges: Vec<(usize, RangeOption<'static>)> =
+        ranges.iter().cloned().enumerate().collect();
+
+    // Narrow down the ranges to only what remains after the position in the direction we are going.
+    // Ranges are disjoint and ordered, so we only have to adjust the one the position was read from.
+    if let Some(position) = position {
+        let key = position.key.clone();
+
+        if backward {
+            ranges.truncate(position.range + 1);
+            let (_, range) = ranges.last_mut().unwrap();
+            range.end = if inclusive {
+                KeySelector::first_greater_than(key)
+            } else {
+                KeySelector::first_greater_or_equal(key)
+            };
+        } else {
+            ranges.drain(..position.range);
+            let (_, range) = ranges.first_mut().unwrap();
+            range.begin = if inclusive {
+                KeySelector::first_greater_or_equal(key)
+            } else {
+                KeySelector::first_greater_than(key)
+            };
+        }
+    }
+
+    // For backward scans we visit the ranges in reverse and read each one in reverse
+    if backward {
+        ranges.reverse();
+    }
+
+    // The ranges are read concurrently, with each one resolving once its first entry has been read. This lets us
+    // read ahead from the following ranges while returning entries from the current one, which matters for scans
+    // with many small ranges like `= ANY(array)`. Entries are still returned in the order of the ranges.
+    stream::iter(ranges)
+        .map(move |(range_index, range_option)| {
+            let range_option = RangeOption {
+                reverse: backward,
+                ..range_option
+            };
+
+            read_range(txn, lookup, range_index, range_option)
+                .boxed()
+                .into_future()
+        })
+        .buffered(CONCURRENT_RANGE_READS)
+        .flat_map(|(first, rest)| stream::iter(first).chain(rest))
+        .fuse()
+        .boxed()
+}
+
+// Space for the split points of a parallel scan in shared memory. If there are too many split points to fit, we
+// use fewer and larger chunks.
+const PARALLEL_CUTS_SIZE: usize = 64 * 1024;
+
+const PARALLEL_NOT_STARTED: u32 = 0;
+const PARALLEL_PLANNING: u32 = 1;
+const PARALLEL_READY: u32 = 2;
+
+// State of a parallel index scan, shared between the leader and the workers. The first participant to start
+// scanning splits the ranges of the scan into chunks at FDB split points, and the participants then take turns
+// claiming the next chunk to read.
+#[repr(C)]
+struct ParallelScanState {
+    // Read version of the leader's transaction, so all participants read the same data
+    read_version: i64,
+    // Set if the leader's transaction has written anything. Those writes are only visible to the leader, so it
+    // does the whole scan on its own.
+    leader_only: bool,
+    status: AtomicU32,
+    // Signalled once the chunks have been planned
+    planned: ConditionVariable,
+    next_chunk: AtomicU32,
+    // The packed cuts between the chunks, see `plan_chunks`
+    cuts_len: u32,
+    cuts: [u8; PARALLEL_CUTS_SIZE],
+}
+
+// A pointer to the shared state of a parallel scan which can be moved into a stream. The shared memory outlives the
+// scan, and the fields which are modified while scanning are atomic.
+struct SharedScanState(*mut ParallelScanState);
+
+unsafe impl Send for SharedScanState {}
+
+impl SharedScanState {
+    // Claims the next chunk to read, returning its position in the scan
+    fn claim_chunk(&self) -> usize {
+        unsafe { (*self.0).next_chunk.fetch_add(1, Ordering::AcqRel) as usize }
+    }
+}
+
+pub unsafe extern "C-unwind" fn amestimateparallelscan(
+    _nkeys: i32,
+    _norderbys: i32,
+) -> pg_sys::Size {
+    size_of::<ParallelScanState>()
+}
+
+// Initialize the shared state of a parallel scan. This is called by the leader before the workers are started.
+pub unsafe extern "C-unwind" fn aminitparallelscan(target: *mut c_void) {
+    unsafe {
+        log!("IAM: Init parallel scan");
+
+        let state = target as *mut ParallelScanState;
+        std::ptr::write_bytes(state, 0, 1);
+
+        let leader_only = crate::transaction::has_any_writes();
+        if leader_only && !pg_sys::parallel_leader_participation {
+            ereport!(
+                ERROR,
+                PgSqlErrorCode::ERRCODE_FEATURE_NOT_SUPPORTED,
+                "parallel pgfdb index scans after writes in the same transaction require parallel_leader_participation"
+            );
+        }
+
+        let txn = crate::transaction::get_transaction();
+        (*state).read_version = txn.get_read_version().block_on().unwrap_or_pg_error();
+        (*state).leader_only = leader_only;
+        pg_sys::ConditionVariableInit(&mut (*state).planned);
+    }
+}
+
+// Reset a parallel scan so it can be run again. This is called by the leader while no workers are running.
+pub unsafe extern "C-unwind" fn amparallelrescan(scan: IndexScanDesc) {
+    unsafe {
+        log!("IAM: Parallel re-scan");
+
+        let state = parallel_scan_state(scan);
+        (*state)
+            .status
+            .store(PARALLEL_NOT_STARTED, Ordering::Release);
+        (*state).next_chunk.store(0, Ordering::Release);
+    }
+}
+
+unsafe fn parallel_scan_state(scan: IndexScanDesc) -> *mut ParallelScanState {
+    unsafe {
+        let parallel_scan = (*scan).parallel_scan;
+        (parallel_scan as *mut u8).add((*parallel_scan).ps_offset) as *mut ParallelScanState
+    }
+}
+
+// Create a stream for this participant's share of a parallel scan. It reads one chunk at a time, claiming the next
+// one when the previous has run out, and looks up the table rows for its entries itself. The chunks are claimed in
+// the order of the scan, so the entries of each participant are still ordered.
+unsafe fn create_parallel_stream(
+    lookup: RowLookup,
+    plan: &ScanPlan,
+    direction: ScanDirection::Type,
+    scan: IndexScanDesc,
+) -> BoxStream<'static, FdbResult<IndexEntry>> {
+    unsafe {
+        let state = parallel_scan_state(scan);
+        if (*state).leader_only {
+            if pg_sys::ParallelWorkerNumber >= 0 {
+                return empty().boxed();
+            }
+            return create_stream(lookup, plan, direction, None, false);
+        }
+
+        crate::transaction::use_read_version((*state).read_version);
+
+        // Skip scans find their distinct values as they go, which can't be split up front. As all entries are
+        // rechecked, we can instead scan everything with the prefix, which may read more entries than needed.
+        let ranges = match plan {
+            ScanPlan::Ranges(ranges) => ranges.clone(),
+            ScanPlan::Skip(skip_scan) => vec![RangeOption::from(skip_scan.prefix.range())],
+        };
+        let cuts = parallel_cuts(state, &ranges);
+        let num_chunks = cuts.len() - 1;
+
+        let backward = direction == ScanDirection::BackwardScanDirection;
+        let shared = SharedScanState(state);
+        let chunks = std::iter::from_fn(move || {
+            let claimed = shared.claim_chunk();
+            if claimed >= num_chunks {
+                return None;
+            }
+
+            let chunk = if backward {
+                num_chunks - 1 - claimed
+            } else {
+                claimed
+            };
+            Some(chunk_ranges(&ranges, &cuts[chunk], &cuts[chunk + 1]))
+        });
+
+        stream::iter(chunks)
+            .flat_map(move |ranges| read_ranges(lookup, &ranges, backward, None, false))
+            .fuse()
+            .boxed()
+    }
+}
 
-        // Construct a range option representing what part of the index we need to iterate over based on the scan keys
-        let index_oid = (*index_relation).rd_id;
-        let index_subspace = crate::subspace::index(index_oid);
-        let range_options = range_options_for_scan(index_subspace, scan_keys, attrs);
+// Get the cuts between the chunks of a parallel scan. The first participant to get here plans them and shares them
+// with the others, who wait until it's done.
+unsafe fn parallel_cuts(
+    state: *mut ParallelScanState,
+    ranges: &[RangeOption<'static>],
+) -> Vec<Cut> {
+    unsafe {
+        let planning = (*state).status.compare_exchange(
+            PARALLEL_NOT_STARTED,
+            PARALLEL_PLANNING,
+            Ordering::AcqRel,
+            Ordering::Acquire,
+        );
+
+        if planning.is_ok() {
+            let txn = crate::transaction::get_transaction();
+            let (cuts, packed) = plan_chunks(txn, ranges, PARALLEL_CUTS_SIZE).unwrap_or_pg_error();
+
+            (*state).cuts[..packed.len()].copy_from_slice(&packed);
+            (*state).cuts_len = packed.len() as u32;
+            (*state).status.store(PARALLEL_READY, Ordering::Release);
+            pg_sys::ConditionVariableBroadcast(&mut (*state).planned);
+            return cuts;
+        }
 
-        let table_oid = (*(*scan).heapRelation).rd_id;
+        while (*state).status.load(Ordering::Acquire) != PARALLEL_READY {
+            pg_sys::ConditionVariableSleep(&mut (*state).planned, pg_sys::PG_WAIT_EXTENSION);
+        }
+        pg_sys::ConditionVariableCancelSleep();
+
+        unpack_cuts(&(*state).cuts[..(*state).cuts_len as usize])
+    }
+}
 
-        // Create a stream of key-value pairs from FDB from all the range options chained together
+impl SkipScan {
+    fn read(
+        &self,
+        lookup: RowLookup,
+        backward: bool,
+        position: Option<&ScanPosition>,
+        inclusive: bool,
+    ) -> BoxStream<'static, FdbResult<IndexEntry>> {
         let txn = crate::transaction::get_transaction();
-        let stream = range_options
+
+        // When picking up from a position, we first finish the ranges of the distinct value it belongs to
+        let (current, next) = match position {
+            Some(position) => {
+                let values = self.skipped_values(&position.key);
+                let ranges = self.ranges_for(&values);
+                let stream = read_ranges(lookup, &ranges, backward, Some(position), inclusive);
+                (stream, self.selector_after(&values, backward))
+            }
+            None => {
+                let (start, end) = self.prefix.range();
+                let selector = if backward {
+                    KeySelector::last_less_than(end)
+                } else {
+                    KeySelector::first_greater_or_equal(start)
+                };
+                (empty().boxed(), selector)
+            }
+        };
+
+        // Find the next distinct value by resolving a key selector which points right past the keys of the
+        // previous one, and then read the ranges for it
+        let skip_scan = self.clone();
+        let rest = stream::unfold(Some(next), move |selector| {
+            let skip_scan = skip_scan.clone();
+            async move {
+                let selector = selector?;
+                let key = match txn.get_key(&selector, false).await {
+                    Ok(key) => key,
+                    Err(err) => return Some((stream::once(async { Err(err) }).boxed(), None)),
+                };
+
+                // Once we end up outside the prefix, there are no more distinct values
+                let (start, end) = skip_scan.prefix.range();
+                if *key < *start || *key >= *end {
+                    return None;
+                }
+
+                let values = skip_scan.skipped_values(&key);
+                let ranges = skip_scan.ranges_for(&values);
+                let stream = read_ranges(lookup, &ranges, backward, None, false);
+                Some((stream, Some(skip_scan.selector_after(&values, backward))))
+            }
+        })
+        .flatten();
+
+        current.chain(rest).fuse().boxed()
+    }
+
+    // The values of the skipped columns for a key in the index
+    fn skipped_values(&self, key: &[u8]) -> Vec<Element<'static>> {
+        let elements: Vec<Element> = self.prefix.unpack(key).unwrap_or_report();
+        elements
             .into_iter()
-            .fold(empty().boxed(), |stream, range_option| {
-                let index_scan = txn
-                    .get_ranges(range_option, false)
-                    .map_ok(move |values| {
-                        index_values_to_table_lookups(
-                            txn,
-                            crate::subspace::table(table_oid),
-                            values,
-                        )
-                    })
-                    .try_flatten();
+            .take(self.skipped_columns)
+            .map(Element::into_owned)
+            .collect()
+    }
 
-                stream.chain(index_scan).boxed()
-            });
+    fn ranges_for(&self, values: &[Element<'static>]) -> Vec<RangeOption<'static>> {
+        ranges_for_conditions(self.prefix.subspace(&values.to_vec()), &self.conditions)
+    }
+
+    // Key selector for the first key of the next distinct value in the direction of the scan
+    fn selector_after(&self, values: &[Element<'static>], backward: bool) -> KeySelector<'static> {
+        let subspace = self.prefix.subspace(&values.to_vec());
+        if backward {
+            KeySelector::last_less_than(subspace.bytes().to_vec())
+        } else {
+            KeySelector::first_greater_or_equal(subspace.range().1)
+        }
+    }
+}
+
+// Reads the entries of a range together with their table rows, one batch at a time. Each batch picks up right after
+// the last entry of the previous one, in the direction of the range.
+fn read_range(
+    txn: &'static Transaction,
+    lookup: RowLookup,
+    range_index: usize,
+    range_option: RangeOption<'static>,
+) -> impl Stream<Item = FdbResult<IndexEntry>> {
+    stream::try_unfold(
+        (Some(range_option), 1),
+        move |(range_option, iteration)| async move {
+            let Some(range_option) = range_option else {
+                return Ok(None);
+            };
 
-        // Replace the existing stream
-        // First, drop the old stream to avoid leaking resources
-        let old_stream = std::ptr::replace(&mut (*fdb_scan).values, stream);
-        drop(old_stream);
+            let (entries, last_key, more) =
+                read_batch(txn, lookup, range_index, &range_option, iteration).await?;
+            let next = match last_key {
+                Some(key) if more => Some(if range_option.reverse {
+                    RangeOption {
+                        end: KeySelector::first_greater_or_equal(key),
+                        ..range_option
+                    }
+                } else {
+                    RangeOption {
+                        begin: KeySelector::first_greater_than(key),
+                        ..range_option
+                    }
+                }),
+                _ => None,
+            };
+
+            Ok(Some((
+                stream::iter(entries.into_iter().map(Ok)),
+                (next, iteration + 1),
+            )))
+        },
+    )
+    .try_flatten()
+}
+
+// Reads a batch of index entries and their table rows. Returns the entries along with the last key read and
+// whether the range has more entries. Entries pointing to rows which no longer exist are skipped.
+//
+// Mapped ranges read the table rows in the same request as the index entries, saving a round trip per batch, but
+// they don't support reading your own writes. We only use them when the transaction hasn't written anything, which
+// is checked for each batch as statements like UPDATE write while their scan is still going. If a mapped range
+// fails with an error that retrying the transaction wouldn't fix, for example because the cluster doesn't support
+// them, we fall back to reading the entries and then looking up their rows concurrently.
+async fn read_batch(
+    txn: &'static Transaction,
+    lookup: RowLookup,
+    range_index: usize,
+    range_option: &RangeOption<'static>,
+    iteration: usize,
+) -> FdbResult<(Vec<IndexEntry>, Option<Vec<u8>>, bool)> {
+    if MAPPED_RANGES.get() && !crate::transaction::has_any_writes() {
+        match txn
+            .get_mapped_range(range_option, &lookup.mapper(), iteration, false)
+            .await
+        {
+            Ok(values) => {
+                let last_key = values.last().map(|value| value.key().to_vec());
+                let more = values.more();
+                let entries = values
+                    .iter()
+                    .filter_map(|value| {
+                        let row = value.range_result().first()?;
+                        Some(IndexEntry {
+                            range: range_index,
+                            key: value.key().to_vec(),
+                            id: id_from_index_key(value.key()),
+                            row: row.value().to_vec(),
+                        })
+                    })
+                    .collect();
+                return Ok((entries, last_key, more));
+            }
+            Err(err) if !err.is_retryable() => {
+                log!("IAM: Falling back from mapped range read, error={}", err);
+            }
+            Err(err) => return Err(err),
+        }
     }
+
+    let values = txn.get_range(range_option, iteration, false).await?;
+    let last_key = values.last().map(|value| value.key().to_vec());
+    let more = values.more();
+    let entries = index_values_to_table_lookups(txn, lookup, range_index, values).await?;
+    Ok((entries, last_key, more))
 }
 
 // Takes a list of FDB values from an index scan and performs point lookups against the table for those rows.
-// The intent here is to schedule all those point lookups in parallel and then convert them into a stream of results
-// with the full table row. This makes for more efficient index scans, compared to just scanning the index and then
+// The intent here is to schedule all those point lookups in parallel, so that looking up the rows of a batch costs a
+// single round trip. This makes for more efficient index scans, compared to just scanning the index and then
 // having the TAM look up each row one by one.
-//
-// This could likely be improved by using mapped ranges in FDB but currently they don't support Read Your Own Writes and
-// will fail hard if reading a value that was written in the same transaction. Maybe one could implement mapped ranges still
-// and fall back to the logic below if that error is encountered. Or alternatively, have some heuristic for chosing between
-// a mapped range or the logic below. Maybe as simple as if any write has been done in the transaction, don't use mapped ranges?
-fn index_values_to_table_lookups(
+async fn index_values_to_table_lookups(
     txn: &'static Transaction,
-    table_subspace: Subspace,
+    lookup: RowLookup,
+    range_index: usize,
     values: FdbValues,
-) -> impl Stream<Item = FdbResult<(u32, FdbSlice)>> {
-    let ids: Vec<u32> = values
+) -> FdbResult<Vec<IndexEntry>> {
+    let table_subspace = crate::subspace::table(lookup.table_oid);
+    let entries: Vec<(Vec<u8>, u32)> = values
+        .iter()
+        .map(|value| (value.key().to_vec(), id_from_index_key(value.key())))
+        .collect();
+
+    let rows = try_join_all(
+        entries
+            .iter()
+            .map(|(_, id)| txn.get(&table_subspace.pack(id), false)),
+    )
+    .await?;
+
+    Ok(entries
         .into_iter()
-        .map(|value| {
-            // Unpack the key to get the tuple elements
-            let key_tuple_elements: Vec<Element> = unpack(value.key()).unwrap_or_report();
+        .zip(rows)
+        .filter_map(|((key, id), row)| {
+            row.map(|row| IndexEntry {
+                range: range_index,
+                key,
+                id,
+                row: row.to_vec(),
+            })
+        })
+        .collect())
+}
+
+// The ID of the row an index key points to, which is the last element of the key
+fn id_from_index_key(key: &[u8]) -> u32 {
+    let elements: Vec<Element> = unpack(key).unwrap_or_report();
+    elements.last().unwrap().as_i64().unwrap() as u32
+}
 
-            // The ID is the last element in the key tuple
-            let id = key_tuple_elements.last().unwrap().as_i64().unwrap() as u32;
+fn scan_plan(
+    index_subspace: Subspace,
+    scan_keys: &[ScanKeyData],
+    attrs: &[FormData_pg_attribute],
+    options: &[i16],
+) -> ScanPlan {
+    // A NULL argument, like `= $1` executed with NULL or `= ANY(NULL::int[])`, can never match, so there is
+    // nothing to scan. The argument isn't a valid datum in that case, so it must not reach `encode_scan_key`.
+    if scan_keys
+        .iter()
+        .any(|scan_key| !scan_key_can_match(scan_key))
+    {
+        return ScanPlan::Ranges(Vec::new());
+    }
 
-            id
+    let columns: Vec<Vec<ScanCondition>> = attrs
+        .iter()
+        .zip(options)
+        .enumerate()
+        .map(|(column, (attr, option))| {
+            let order = ColumnOrder::from_index_option(*option);
+            scan_keys
+                .iter()
+                .filter(|scan_key| scan_key.sk_attno as usize == column + 1)
+                .flat_map(|scan_key| encode_scan_key(scan_key, attr, order))
+                .collect()
         })
         .collect();
 
-    let future = join_all(ids.iter().map(|id| {
-        let id = id.clone();
-        txn.get(&table_subspace.pack(&id), false)
-            .map_ok(move |result| result.map(|slice| (id, slice)))
-    }));
-
-    let nested_stream = stream::once(future.map(stream::iter));
-    nested_stream.flatten().filter_map(async |item| match item {
-        Ok(result) => match result {
-            Some(result) => Some(Ok(result)),
-            None => None,
-        },
-        Err(err) => Some(Err(err)),
-    })
+    // Leading columns with a single value form a prefix. If the column after them doesn't have any conditions
+    // but a later column does, we use a skip scan so that the conditions on the later columns can still be used
+    // to narrow down the ranges.
+    let mut prefix_elements: Vec<Element> = Vec::new();
+    for (column, conditions) in columns.iter().enumerate() {
+        if conditions.is_empty() {
+            let skipped_columns = columns[column..]
+                .iter()
+                .take_while(|conditions| conditions.is_empty())
+                .count();
+            let trailing = &columns[column + skipped_columns..];
+            if trailing.is_empty() {
+                break;
+            }
+
+            let prefix = if prefix_elements.is_empty() {
+                index_subspace.clone()
+            } else {
+                index_subspace.subspace(&prefix_elements)
+            };
+
+            // The trailing conditions are the same for each distinct value, so we can check up front if they
+            // contradict each other
+            if ranges_for_conditions(prefix.clone(), trailing).is_empty() {
+                return ScanPlan::Ranges(Vec::new());
+            }
+
+            return ScanPlan::Skip(SkipScan {
+                prefix,
+                skipped_columns,
+                conditions: trailing.to_vec(),
+            });
+        }
+
+        match ColumnRange::from_conditions(conditions.clone()).map(|range| range.single_value()) {
+            Some(Some(element)) => prefix_elements.push(element),
+            _ => break,
+        }
+    }
+
+    ScanPlan::Ranges(ranges_for_conditions(index_subspace, &columns))
 }
 
-fn range_options_for_scan<'a>(
-    index_subspace: Subspace,
-    scan_keys: &'a [ScanKeyData],
-    attrs: &'a [FormData_pg_attribute],
-) -> Vec<RangeOption<'a>> {
-    // This should never happen but if there are no scan keys, we scan the entire index
-    let [head @ .., last] = scan_keys else {
-        return vec![RangeOption::from(index_subspace.range())];
-    };
+// Builds the ranges to scan within a subspace for the conditions on each of the columns that follow it
+fn ranges_for_conditions(
+    subspace: Subspace,
+    columns: &[Vec<ScanCondition>],
+) -> Vec<RangeOption<'static>> {
+    // Leading columns which are compared with equality form a prefix shared by all keys we need to scan. The first
+    // column without an equality condition bounds the range within that prefix. Conditions on the columns after it
+    // can't narrow down the range further and are instead applied when the rows are rechecked.
+    let mut prefix_elements: Vec<Element> = Vec::new();
+    let mut bound = None;
 
-    let mut head_tuple_elements: Vec<Element> = Vec::with_capacity(head.len());
+    for conditions in columns {
+        if conditions.is_empty() {
+            break;
+        }
 
-    // If there are more than one scan key (i.e. multi-column index), then only the final scan key
-    // can use a non-equality operator.
-    for head_scan_key in head {
-        // Must use equality operator (we can probably support inequality as well by splitting into more ranges)
-        if head_scan_key.sk_strategy != 3 {
-            panic!(
-                "IAM: Only equality operators are supported for multi-column index scans on non-final scan keys"
-            );
+        // If the conditions contradict each other, no rows can match and we can skip the scan entirely
+        let Some(column_range) = ColumnRange::from_conditions(conditions.clone()) else {
+            return Vec::new();
+        };
+
+        if let Some(element) = column_range.single_value() {
+            prefix_elements.push(element);
+            continue;
         }
 
-        let attr = attrs[head_scan_key.sk_attno as usize - 1];
-        let element = encode_datum_for_index(head_scan_key.sk_argument, attr.atttypid);
-        head_tuple_elements.push(element);
+        bound = Some(column_range);
+        break;
     }
 
-    // If we have a multi-column index and query, we will now have some `head_tuple_elements`
+    // If we have a multi-column index and query, we will now have some `prefix_elements`
     // and can create a new prefix for our search
-    let base_subspace = if head_tuple_elements.is_empty() {
-        index_subspace
+    let base_subspace = if prefix_elements.is_empty() {
+        subspace
     } else {
-        index_subspace.subspace(&head_tuple_elements)
+        subspace.subspace(&prefix_elements)
     };
 
-    // We are now at the final part of the range building for the final column of the scan keys.
-    // Here we can support more than just equality by building different ranges to scan.
-    let attr = attrs[last.sk_attno as usize - 1];
+    match bound {
+        Some(column_range) => column_range.range_options(&base_subspace),
+        // Without any other conditions we scan everything with the prefix
+        None => vec![RangeOption::from(base_subspace.range())],
+    }
+}
 
-    let element = if last.sk_flags as u32 & SK_SEARCHNULL != 0
-        || last.sk_flags as u32 & SK_SEARCHNOTNULL != 0
-    {
-        // If this is an IS NULL or IS NOT NULL scan, we shouldn't encode and instead just use the tuple nil value
-        Element::Nil
-    } else {
-        encode_datum_for_index(last.sk_argument, attr.atttypid)
-    };
+// A bound on the values of a column. The element is kept packed as well, as the order of packed elements is the
+// order of the index keys.
+struct ColumnBound {
+    element: Element<'static>,
+    packed: Vec<u8>,
+    inclusive: bool,
+}
+
+impl ColumnBound {
+    fn new(element: Element<'static>, inclusive: bool) -> Self {
+        ColumnBound {
+            packed: foundationdb::tuple::pack(&element),
+            element,
+            inclusive,
+        }
+    }
+}
+
+// A condition on a column from a scan key, either a comparison with a strategy or, for `= ANY(array)`, a set of
+// values where one of them must match
+#[derive(Clone)]
+enum ScanCondition {
+    Compare(u16, Element<'static>),
+    AnyOf(Vec<Element<'static>>),
+}
+
+// The values of a column which can match all the conditions on it, built by intersecting the conditions into a
+// single interval with some values excluded from it by not equal conditions. If there are any `= ANY(array)`
+// conditions, only the values in all of those arrays within the interval can match.
+struct ColumnRange {
+    lower: Option<ColumnBound>,
+    upper: Option<ColumnBound>,
+    excluded: Vec<Vec<u8>>,
+    values: Option<Vec<ColumnBound>>,
+}
+
+impl ColumnRange {
+    // Returns None if the conditions can't all be satisfied
+    fn from_conditions(conditions: Vec<ScanCondition>) -> Option<Self> {
+        let mut range = ColumnRange {
+            lower: None,
+            upper: None,
+            excluded: Vec::new(),
+            values: None,
+        };
+
+        for condition in conditions {
+            let (strategy, element) = match condition {
+                ScanCondition::Compare(strategy, element) => (strategy, element),
+                ScanCondition::AnyOf(elements) => {
+                    range.restrict_values(elements);
+                    continue;
+                }
+            };
+
+            match strategy {
+                1 => range.restrict_upper(ColumnBound::new(element, false)),
+                2 => range.restrict_upper(ColumnBound::new(element, true)),
+                3 => {
+                    range.restrict_lower(ColumnBound::new(element.clone(), true));
+                    range.restrict_upper(ColumnBound::new(element, true));
+                }
+                4 => range.restrict_lower(ColumnBound::new(element, true)),
+                5 => range.restrict_lower(ColumnBound::new(element, false)),
+                6 => range.excluded.push(foundationdb::tuple::pack(&element)),
+                _ => ereport!(
+                    ERROR,
+                    PgSqlErrorCode::ERRCODE_FEATURE_NOT_SUPPORTED,
+                    &format!("pgfdb index scans don't support strategy {strategy}")
+                ),
+            }
+        }
 
-    // Based on what the operator (strategy) is for the final key, construct the final search range
-    match last.sk_strategy {
-        // Strategy 1: Less than (<)
-        1 => {
-            let start_key = KeySelector::first_greater_or_equal(base_subspace.range().0);
-            let end_key = KeySelector::last_less_than(base_subspace.subspace(&element).range().1);
-            vec![RangeOption::from((start_key, end_key))]
+        if let (Some(lower), Some(upper)) = (&range.lower, &range.upper) {
+            let is_empty = lower.packed > upper.packed
+                || (lower.packed == upper.packed && !(lower.inclusive && upper.inclusive));
+            // A single value can also be ruled out by a not equal condition
+            let is_excluded = lower.packed == upper.packed
+                && range.excluded.iter().any(|packed| *packed == lower.packed);
+
+            if is_empty || is_excluded {
+                return None;
+            }
         }
-        // Strategy 2: Less than or equal (<=)
-        2 => {
-            let start_key = KeySelector::first_greater_or_equal(base_subspace.range().0);
-            // End is exclusive in ranges so we need to use this key selector to include the element value
-            let end_key =
-                KeySelector::first_greater_than(base_subspace.subspace(&element).range().1);
-            vec![RangeOption::from((start_key, end_key))]
+
+        // Only keep the excluded values which are inside the interval, in index order
+        range.excluded.sort();
+        range.excluded.dedup();
+        let (lower, upper) = (&range.lower, &range.upper);
+        range.excluded.retain(|packed| {
+            lower.as_ref().is_none_or(|lower| *packed > lower.packed)
+                && upper.as_ref().is_none_or(|upper| *packed < upper.packed)
+        });
+
+        // Only keep the values which satisfy all other conditions, in index order
+        if let Some(mut values) = range.values.take() {
+            values.retain(|value| range.contains(&value.packed));
+            values.sort_by(|a, b| a.packed.cmp(&b.packed));
+            values.dedup_by(|a, b| a.packed == b.packed);
+
+            if values.is_empty() {
+                return None;
+            }
+
+            range.values = Some(values);
         }
-        // Strategy 3: Equality (=)
-        // Also covers IS NULL scans
-        i if i == 3 || last.sk_flags as u32 & SK_SEARCHNULL != 0 => {
-            vec![RangeOption::from(base_subspace.subspace(&element).range())]
+
+        Some(range)
+    }
+
+    fn contains(&self, packed: &[u8]) -> bool {
+        let above_lower = self.lower.as_ref().is_none_or(|lower| {
+            packed > lower.packed.as_slice() || (lower.inclusive && packed == lower.packed)
+        });
+        let below_upper = self.upper.as_ref().is_none_or(|upper| {
+            packed < upper.packed.as_slice() || (upper.inclusive && packed == upper.packed)
+        });
+
+        above_lower && below_upper && !self.excluded.iter().any(|excluded| excluded == packed)
+    }
+
+    fn restrict_values(&mut self, elements: Vec<Element<'static>>) {
+        let values: Vec<ColumnBound> = elements
+            .into_iter()
+            .map(|element| ColumnBound::new(element, true))
+            .collect();
+
+        self.values = Some(match self.values.take() {
+            None => values,
+            Some(existing) => existing
+                .into_iter()
+                .filter(|value| values.iter().any(|other| other.packed == value.packed))
+                .collect(),
+        });
+    }
+
+    fn restrict_lower(&mut self, bound: ColumnBound) {
+        let is_tighter = match &self.lower {
+            None => true,
+            Some(lower) => {
+                bound.packed > lower.packed || (bound.packed == lower.packed && !bound.inclusive)
+            }
+        };
+
+        if is_tighter {
+            self.lower = Some(bound);
         }
-        // Strategy 6: Not equals (!=)
-        // Also covers IS NOT NULL scans
-        i if i == 6 || last.sk_flags as u32 & SK_SEARCHNOTNULL != 0 => {
-            // For not equals, we take the inverse of equals above and hence need two ranges:
-            // 1. Everything from the start of the subspace up to (but not including) the start of the equals subspace
-            // 2. Everything after the equals subspace to the end of the subspace
-            let equals_subspace = base_subspace.subspace(&element);
-
-            let before_range = RangeOption::from((
-                KeySelector::first_greater_or_equal(base_subspace.range().0),
-                KeySelector::first_greater_or_equal(equals_subspace.range().0),
-            ));
-
-            let after_range = RangeOption::from((
-                KeySelector::first_greater_than(equals_subspace.range().1),
-                KeySelector::first_greater_than(base_subspace.range().1),
-            ));
-
-            vec![before_range, after_range]
+    }
+
+    fn restrict_upper(&mut self, bound: ColumnBound) {
+        let is_tighter = match &self.upper {
+            None => true,
+            Some(upper) => {
+                bound.packed < upper.packed || (bound.packed == upper.packed && !bound.inclusive)
+            }
+        };
+
+        if is_tighter {
+            self.upper = Some(bound);
         }
-        // Strategy 4: Greater than or equal (>=)
-        4 => {
-            // For greater than or equal, we start from the element itself
-            let start_key =
-                KeySelector::first_greater_or_equal(base_subspace.subspace(&element).range().0);
-            let end_key = KeySelector::first_greater_than(base_subspace.range().1);
-            vec![RangeOption::from((start_key, end_key))]
+    }
+
+    // If only a single value can match, for example for equality conditions, returns that value
+    fn single_value(&self) -> Option<Element<'static>> {
+        if let Some(values) = &self.values {
+            return match values.as_slice() {
+                [value] => Some(value.element.clone()),
+                _ => None,
+            };
         }
-        // Strategy 5: Greater than (>)
-        5 => {
-            // For greater than, we need to start from the element and go to the end of the subspace
-            let start_key =
-                KeySelector::first_greater_than(base_subspace.subspace(&element).range().1);
-            let end_key = KeySelector::first_greater_than(base_subspace.range().1);
-            vec![RangeOption::from((start_key, end_key))]
+
+        match (&self.lower, &self.upper) {
+            (Some(lower), Some(upper)) if lower.packed == upper.packed => {
+                Some(lower.element.clone())
+            }
+            _ => None,
         }
-        _ => panic!("Unsupported strategy for scan key {}", last.sk_strategy),
+    }
+
+    // Builds the ranges of keys to scan within the subspace of the preceding columns. The interval is split into
+    // several ranges around any excluded values. If there's a set of values, we scan one range per value.
+    fn range_options(&self, base_subspace: &Subspace) -> Vec<RangeOption<'static>> {
+        let key = |packed: &[u8], after: bool| {
+            let mut key = base_subspace.bytes().to_vec();
+            key.extend_from_slice(packed);
+            // All keys starting with a value sort before the value followed by 0xFF
+            if after {
+                key.push(0xFF);
+            }
+            key
+        };
+
+        if let Some(values) = &self.values {
+            return values
+                .iter()
+                .map(|value| {
+                    RangeOption::from((key(&value.packed, false), key(&value.packed, true)))
+                })
+                .collect();
+        }
+
+        let mut start = match &self.lower {
+            Some(lower) => key(&lower.packed, !lower.inclusive),
+            None => base_subspace.range().0,
+        };
+        let end = match &self.upper {
+            Some(upper) => key(&upper.packed, upper.inclusive),
+            None => base_subspace.range().1,
+        };
+
+        let mut range_options = Vec::with_capacity(self.excluded.len() + 1);
+        for excluded in &self.excluded {
+            range_options.push(RangeOption::from((start, key(excluded, false))));
+            start = key(excluded, true);
+        }
+        range_options.push(RangeOption::from((start, end)));
+
+        range_options
+    }
+}
+
+// Whether a scan key can match any rows, which isn't the case when its argument is NULL. IS NULL and IS NOT NULL
+// scans also have a NULL argument, but don't use it.
+fn scan_key_can_match(scan_key: &ScanKeyData) -> bool {
+    let flags = scan_key.sk_flags as u32;
+    flags & (SK_SEARCHNULL | SK_SEARCHNOTNULL) != 0 || flags & SK_ISNULL == 0
+}
+
+// Encodes a scan key into conditions on the keys of its column. The argument must not be NULL (see
+// `scan_key_can_match`). IS NULL scans are handled as equality with NULL
+// and IS NOT NULL scans as not equal to NULL, using the tuple nil value rather than encoding an argument.
+fn encode_scan_key(
+    scan_key: &ScanKeyData,
+    attr: &FormData_pg_attribute,
+    order: ColumnOrder,
+) -> Vec<ScanCondition> {
+    let flags = scan_key.sk_flags as u32;
+
+    let condition = if flags & SK_SEARCHNULL != 0 {
+        ScanCondition::Compare(3, Element::Nil)
+    } else if flags & SK_SEARCHNOTNULL != 0 {
+        ScanCondition::Compare(6, Element::Nil)
+    } else if flags & SK_SEARCHARRAY != 0 {
+        // NULL elements can never match, so we skip those
+        let (element_type, values) = deconstruct_array_datum(scan_key.sk_argument);
+        let elements = values
+            .into_iter()
+            .flatten()
+            .map(|value| encode_datum_for_index(value, element_type, attr.attcollation))
+            .collect();
+
+        any_condition(scan_key.sk_strategy, elements)
+    } else {
+        let element = encode_datum_for_index(
+            scan_key.sk_argument,
+            argument_type(scan_key, attr),
+            attr.attcollation,
+        );
+        ScanCondition::Compare(scan_key.sk_strategy, element)
+    };
+
+    key_conditions(condition, order)
+}
+
+// Converts a condition on the values of a column into conditions on its keys, which are ordered based on the DESC
+// and NULLS FIRST/LAST options of the column (see `ColumnOrder`). Apart from IS NULL, conditions never match NULLs,
+// so we also add a condition which excludes them from the range.
+fn key_conditions(condition: ScanCondition, order: ColumnOrder) -> Vec<ScanCondition> {
+    let not_null = if order.nulls_first {
+        ScanCondition::Compare(5, order.null())
+    } else {
+        ScanCondition::Compare(1, order.null())
+    };
+
+    match condition {
+        ScanCondition::Compare(3, Element::Nil) => vec![ScanCondition::Compare(3, order.null())],
+        ScanCondition::Compare(6, Element::Nil) => vec![not_null],
+        ScanCondition::Compare(strategy, element) => {
+            // Descending columns store larger values first, so comparisons are reversed
+            let strategy = match (order.descending, strategy) {
+                (true, 1) => 5,
+                (true, 2) => 4,
+                (true, 4) => 2,
+                (true, 5) => 1,
+                (_, strategy) => strategy,
+            };
+
+            vec![
+                ScanCondition::Compare(strategy, order.encode(element)),
+                not_null,
+            ]
+        }
+        ScanCondition::AnyOf(elements) => {
+            let elements = elements
+                .into_iter()
+                .map(|element| order.encode(element))
+                .collect();
+            vec![ScanCondition::AnyOf(elements)]
+        }
+    }
+}
+
+// Combines the conditions for the elements of an `op ANY(array)` scan key, which matches if any of them do. Apart
+// from equality, that's the same as the least restrictive of the conditions.
+fn any_condition(strategy: u16, elements: Vec<Element<'static>>) -> ScanCondition {
+    match strategy {
+        3 => ScanCondition::AnyOf(elements),
+        // Not equal to any of several values matches all non-null values
+        6 => ScanCondition::Compare(6, Element::Nil),
+        _ => {
+            let bound = elements
+                .into_iter()
+                .map(|element| ColumnBound::new(element, matches!(strategy, 2 | 4)))
+                .reduce(|current, next| {
+                    let is_less_restrictive = match strategy {
+                        // Upper bounds, the highest one is the least restrictive
+                        1 | 2 => next.packed > current.packed,
+                        // Lower bounds, the lowest one is the least restrictive
+                        _ => next.packed < current.packed,
+                    };
+                    if is_less_restrictive { next } else { current }
+                })
+                .unwrap();
+
+            ScanCondition::Compare(strategy, bound.element)
+        }
+    }
+}
+
+// Cross-type operators, like comparing an INTEGER column to a BIGINT, pass an argument of a different type than
+// the column. The argument type is then stored as the subtype of the scan key. Operators of polymorphic operator
+// classes, like the ones for arrays and enums, have a pseudo-type as subtype and then the argument has the same
+// type as the column.
+fn argument_type(scan_key: &ScanKeyData, attr: &FormData_pg_attribute) -> Oid {
+    if scan_key.sk_subtype != InvalidOid
+        && unsafe { pg_sys::get_typtype(scan_key.sk_subtype) } != pg_sys::TYPTYPE_PSEUDO as i8
+    {
+        scan_key.sk_subtype
+    } else {
+        attr.atttypid
     }
 }
 
@@ -362,7 +1479,11 @@ pub unsafe extern "C-unwind" fn amendscan(scan: IndexScanDesc) {
 
     let fdb_scan = scan as *mut FdbIndexScan;
 
-    // Take ownership of the stream to drop it
-    let stream = unsafe { std::ptr::read(&(*fdb_scan).values) };
-    drop(stream);
+    // Take ownership of the stream and scan state to drop them
+    unsafe {
+        drop(std::ptr::read(&(*fdb_scan).values));
+        drop(std::ptr::read(&(*fdb_scan).plan));
+        drop(std::ptr::read(&(*fdb_scan).position));
+        drop(std::ptr::read(&(*fdb_scan).mark));
+    }
 }
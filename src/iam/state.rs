@@ -0,0 +1,148 @@
+use foundationdb::{
+    FdbResult, Transaction,
+    tuple::{Element, pack, unpack},
+};
+use futures::future::join_all;
+use pg_sys::{
+    IndexOptInfo, Oid, PlannerInfo, RelFileNumber, RelOptInfo, Relation,
+    get_relation_info_hook_type,
+};
+use pgrx::{list::List, memcx::current_context, prelude::*};
+use pollster::FutureExt;
+
+use crate::errors::FdbErrorExt;
+
+// The build state of an index, stored in FDB under its relfilenumber like the index keys. While an index is being
+// built, writes to the table keep it up to date, but it can't be used for scans until the build has finished.
+// Indexes without a stored state are readable.
+#[derive(Debug, PartialEq)]
+pub enum IndexState {
+    // The index is being built. All table rows up to and including the cursor key have been indexed by the batches
+    // of the build so far, which lets a batch that is retried continue after the last committed one.
+    WriteOnly { cursor: Option<Vec<u8>> },
+    // The index is complete and can be used for scans
+    Readable,
+}
+
+impl IndexState {
+    fn serialize(&self) -> Vec<u8> {
+        match self {
+            IndexState::WriteOnly { cursor: None } => pack(&("writeonly",)),
+            IndexState::WriteOnly {
+                cursor: Some(cursor),
+            } => pack(&("writeonly", Element::Bytes(cursor.clone().into()))),
+            IndexState::Readable => pack(&("readable",)),
+        }
+    }
+
+    fn deserialize(value: &[u8]) -> Self {
+        let elements: Vec<Element> = unpack(value).unwrap_or_report();
+        match elements.as_slice() {
+            [Element::String(state)] if state == "readable" => IndexState::Readable,
+            [Element::String(state)] if state == "writeonly" => {
+                IndexState::WriteOnly { cursor: None }
+            }
+            [Element::String(state), Element::Bytes(cursor)] if state == "writeonly" => {
+                IndexState::WriteOnly {
+                    cursor: Some(cursor.to_vec()),
+                }
+            }
+            _ => error!("invalid index state {:?}", elements),
+        }
+    }
+}
+
+pub fn get(
+    txn: &Transaction,
+    relfilenumber: RelFileNumber,
+    snapshot: bool,
+) -> FdbResult<Option<IndexState>> {
+    let key = crate::subspace::index_state(relfilenumber);
+    let value = txn.get(key.bytes(), snapshot).block_on()?;
+    Ok(value.map(|value| IndexState::deserialize(&value)))
+}
+
+pub fn set(txn: &Transaction, relfilenumber: RelFileNumber, state: IndexState) {
+    let key = crate::subspace::index_state(relfilenumber);
+    txn.set(key.bytes(), &state.serialize());
+}
+
+static mut PREV_GET_RELATION_INFO_HOOK: get_relation_info_hook_type = None;
+
+pub fn init() {
+    #[allow(static_mut_refs)]
+    unsafe {
+        PREV_GET_RELATION_INFO_HOOK = pg_sys::get_relation_info_hook;
+        pg_sys::get_relation_info_hook = Some(get_relation_info);
+    }
+}
+
+// Planner hook which hides indexes that are still being built, so that they are only used once readable
+#[pg_guard]
+unsafe extern "C-unwind" fn get_relation_info(
+    root: *mut PlannerInfo,
+    relation_oid: Oid,
+    inhparent: bool,
+    rel: *mut RelOptInfo,
+) {
+    unsafe {
+        if let Some(prev_hook) = PREV_GET_RELATION_INFO_HOOK {
+            prev_hook(root, relation_oid, inhparent, rel);
+        }
+
+        let am_oid = pg_sys::get_index_am_oid(c"pgfdb".as_ptr(), true);
+        let indexes: Vec<*mut IndexOptInfo> = current_context(|ctx| {
+            let indexes: List<*mut std::ffi::c_void> =
+                List::downcast_ptr_in_memcx((*rel).indexlist, ctx).unwrap();
+            indexes
+                .iter()
+                .map(|index| *index as *mut IndexOptInfo)
+                .filter(|index| (**index).relam == am_oid)
+                .collect()
+        });
+        if indexes.is_empty() {
+            return;
+        }
+
+        // Once an index is readable it stays that way, as rebuilding an index gives it new storage and with that a
+        // new relcache entry. We remember readable indexes in their relcache entry, so only the states of indexes
+        // we haven't seen as readable yet, usually those still being built, are read from FDB on each planning.
+        let relations: Vec<Relation> = indexes
+            .iter()
+            .map(|index| pg_sys::RelationIdGetRelation((**index).indexoid))
+            .collect();
+        let unknown: Vec<(*mut IndexOptInfo, Relation)> = indexes
+            .into_iter()
+            .zip(relations.iter().copied())
+            .filter(|(_, relation)| (**relation).rd_amcache.is_null())
+            .collect();
+        if !unknown.is_empty() {
+            // Read the states concurrently. These are snapshot reads as the state only decides which indexes the
+            // planner considers, and shouldn't make us conflict with builds.
+            let txn = crate::transaction::get_transaction();
+            let states = join_all(unknown.iter().map(|(_, relation)| {
+                let key = crate::subspace::index_state((**relation).rd_locator.relNumber);
+                txn.get(key.bytes(), true)
+            }))
+            .block_on();
+
+            for ((index, relation), state) in unknown.into_iter().zip(states) {
+                let readable = match state.unwrap_or_pg_error() {
+                    Some(value) => IndexState::deserialize(&value) == IndexState::Readable,
+                    None => true,
+                };
+
+                if readable {
+                    // The cache is freed along with the relcache entry, and its contents don't matter
+                    (*relation).rd_amcache = pg_sys::MemoryContextAlloc((*relation).rd_indexcxt, 1);
+                } else {
+                    (*rel).indexlist = pg_sys::list_delete_ptr((*rel).indexlist, index.cast());
+                }
+            }
+        }
+
+        for relation in relations {
+            pg_sys::RelationClose(relation);
+        }
+    }
+}
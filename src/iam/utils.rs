@@ -1,17 +1,29 @@
 use foundationdb::tuple::Element;
 use foundationdb::tuple::Uuid;
+use foundationdb::tuple::Versionstamp;
 use pg_sys::{Datum, Oid};
+use pgrx::AnyNumeric;
 use pgrx::prelude::*;
 use std::borrow::Cow;
 
+// Postgres sorts NULLs after all other values by default, so we store them as the largest element a tuple can
+// hold: a complete versionstamp with all bits set, which no indexed value is encoded as.
+pub fn null_element<'a>() -> Element<'a> {
+    Element::Versionstamp(Versionstamp::complete([0xFF; 10], u16::MAX))
+}
+
 // Helper function to encode a Postgres datum into an FDB tuple element
-// This function will need to be implemented to handle different Postgres types
-pub fn encode_datum_for_index<'a>(datum: Datum, type_oid: Oid) -> Element<'a> {
+// This function will need to be implemented to handle different Postgres types. The collation is the one of the
+// index column and is only used for collatable types.
+pub fn encode_datum_for_index<'a>(datum: Datum, type_oid: Oid, collation: Oid) -> Element<'a> {
+    // Domains are compared like their base type
+    let type_oid = unsafe { pg_sys::getBaseType(type_oid) };
+
     match type_oid {
         // INT4/INTEGER (OID 23)
         pg_sys::INT4OID => {
-            // Convert the datum to a Rust i32
-            let value = unsafe { pg_sys::DatumGetInt64(datum) };
+            // Convert the datum to a Rust i32, then to i64 for storage
+            let value = datum.value() as i32 as i64;
             Element::Int(value)
         }
         // SMALLINT (OID 21)
@@ -30,33 +42,529 @@ pub fn encode_datum_for_index<'a>(datum: Datum, type_oid: Oid) -> Element<'a> {
         pg_sys::VARCHAROID | pg_sys::TEXTOID => {
             // Use pgrx's text_to_rust_str_unchecked to convert to a Rust string
             let varlena: PgVarlena<()> = unsafe { PgVarlena::from_datum(datum) };
-            let text = unsafe { pgrx::text_to_rust_str_unchecked(varlena.into_pg()).to_string() };
-            Element::String(Cow::Owned(text))
+            let text = unsafe { pgrx::text_to_rust_str_unchecked(varlena.into_pg()) };
+            encode_text(text, collation)
         }
         // REAL/FLOAT4 (OID 700)
         pg_sys::FLOAT4OID => {
             // Convert the datum to a Rust f32, then to f64 for storage in FDB
             let value = unsafe { pg_sys::DatumGetFloat4(datum) as f64 };
-            Element::Double(value)
+            Element::Double(normalize_float(value))
         }
         // DOUBLE PRECISION/FLOAT8 (OID 701)
         pg_sys::FLOAT8OID => {
             // Convert the datum to a Rust f64 for storage in FDB
             let value = unsafe { pg_sys::DatumGetFloat8(datum) };
-            Element::Double(value)
+            Element::Double(normalize_float(value))
         }
         // UUID (OID 2950)
         pg_sys::UUIDOID => {
             let uuid: pgrx::Uuid = unsafe { pgrx::Uuid::from_datum(datum, false).unwrap() };
             Element::Uuid(Uuid::from_bytes(*uuid.as_bytes()))
         }
-        // Add more types as needed
+        // CHARACTER/BPCHAR (OID 1042)
+        pg_sys::BPCHAROID => {
+            // Trailing spaces are insignificant when comparing bpchar values, so we strip them to make equal
+            // values have equal keys
+            let varlena: PgVarlena<()> = unsafe { PgVarlena::from_datum(datum) };
+            let text = unsafe { pgrx::text_to_rust_str_unchecked(varlena.into_pg()) };
+            encode_text(text.trim_end_matches(' '), collation)
+        }
+        // "CHAR" (OID 18)
+        pg_sys::CHAROID => {
+            // Single byte type which Postgres compares as unsigned
+            let value = datum.value() as u8 as i64;
+            Element::Int(value)
+        }
+        // BOOLEAN (OID 16)
+        pg_sys::BOOLOID => {
+            // FDB orders false before true, just like Postgres
+            let value = datum.value() != 0;
+            Element::Bool(value)
+        }
+        // BYTEA (OID 17)
+        pg_sys::BYTEAOID => {
+            // FDB byte strings are ordered byte by byte with shorter prefixes first, which matches Postgres
+            let bytes = unsafe { <&[u8]>::from_datum(datum, false).unwrap() };
+            Element::Bytes(bytes.to_vec().into())
+        }
+        // TIMESTAMP (OID 1114) and TIMESTAMPTZ (OID 1184)
+        pg_sys::TIMESTAMPOID | pg_sys::TIMESTAMPTZOID => {
+            // Both are stored as microseconds since 2000-01-01 (UTC for TIMESTAMPTZ), with infinities
+            // represented as the minimum and maximum values
+            let value = datum.value() as i64;
+            Element::Int(value)
+        }
+        // DATE (OID 1082)
+        pg_sys::DATEOID => {
+            // Stored as days since 2000-01-01, with infinities represented as the minimum and maximum values
+            let value = datum.value() as i32 as i64;
+            Element::Int(value)
+        }
+        // TIME (OID 1083)
+        pg_sys::TIMEOID => {
+            // Stored as microseconds since midnight
+            let value = datum.value() as i64;
+            Element::Int(value)
+        }
+        // INTERVAL (OID 1186)
+        pg_sys::INTERVALOID => {
+            let interval = unsafe { &*datum.cast_mut_ptr::<pg_sys::Interval>() };
+            Element::Bytes(encode_interval(interval).to_vec().into())
+        }
+        // NUMERIC (OID 1700)
+        pg_sys::NUMERICOID => {
+            let numeric = unsafe { AnyNumeric::from_datum(datum, false).unwrap() };
+            Element::Bytes(encode_numeric(&numeric.to_string()).into())
+        }
+        // Enums, arrays and composite types don't have fixed OIDs
+        _ if unsafe { pg_sys::type_is_enum(type_oid) } => encode_enum(datum),
+        _ if unsafe { pg_sys::get_element_type(type_oid) } != pg_sys::InvalidOid => {
+            encode_array(datum, collation)
+        }
+        _ if unsafe { pg_sys::type_is_rowtype(type_oid) } => encode_composite(datum),
+        // Indexes on unsupported types are rejected when they are built (see `is_supported_type`), so we should
+        // never end up here
         _ => {
-            // Log unsupported types
-            panic!(
-                "IAM: encode_datum_for_index not yet implemented for type OID: {}",
-                type_oid.to_u32()
+            ereport!(
+                ERROR,
+                PgSqlErrorCode::ERRCODE_FEATURE_NOT_SUPPORTED,
+                &format!(
+                    "pgfdb indexes don't support data type {}",
+                    type_name(type_oid)
+                )
+            );
+        }
+    }
+}
+
+// Checks if values of a type can be encoded by `encode_datum_for_index`. Arrays and composite types are supported
+// if their elements and fields are. Anonymous records can only be checked once we have a value, so we accept those.
+pub fn is_supported_type(type_oid: Oid) -> bool {
+    let type_oid = unsafe { pg_sys::getBaseType(type_oid) };
+
+    match type_oid {
+        pg_sys::INT2OID
+        | pg_sys::INT4OID
+        | pg_sys::INT8OID
+        | pg_sys::TEXTOID
+        | pg_sys::VARCHAROID
+        | pg_sys::BPCHAROID
+        | pg_sys::CHAROID
+        | pg_sys::FLOAT4OID
+        | pg_sys::FLOAT8OID
+        | pg_sys::UUIDOID
+        | pg_sys::BOOLOID
+        | pg_sys::BYTEAOID
+        | pg_sys::TIMESTAMPOID
+        | pg_sys::TIMESTAMPTZOID
+        | pg_sys::DATEOID
+        | pg_sys::TIMEOID
+        | pg_sys::INTERVALOID
+        | pg_sys::NUMERICOID
+        | pg_sys::RECORDOID => true,
+        _ if unsafe { pg_sys::type_is_enum(type_oid) } => true,
+        _ if unsafe { pg_sys::get_element_type(type_oid) } != pg_sys::InvalidOid => {
+            is_supported_type(unsafe { pg_sys::get_element_type(type_oid) })
+        }
+        _ if unsafe { pg_sys::type_is_rowtype(type_oid) } => unsafe {
+            let tuple_desc = pg_sys::lookup_rowtype_tupdesc(type_oid, -1);
+            let supported = (*tuple_desc)
+                .attrs
+                .as_slice((*tuple_desc).natts as usize)
+                .iter()
+                .filter(|attr| !attr.attisdropped)
+                .all(|attr| is_supported_type(attr.atttypid));
+            pg_sys::DecrTupleDescRefCount(tuple_desc);
+            supported
+        },
+        _ => false,
+    }
+}
+
+// Checks if values of a type contain values of another type, either as the type itself or through domains, arrays
+// and composite types
+pub fn type_contains(type_oid: Oid, contained_oid: Oid) -> bool {
+    let type_oid = unsafe { pg_sys::getBaseType(type_oid) };
+
+    match type_oid {
+        _ if type_oid == contained_oid => true,
+        pg_sys::RECORDOID => false,
+        _ if unsafe { pg_sys::get_element_type(type_oid) } != pg_sys::InvalidOid => {
+            type_contains(unsafe { pg_sys::get_element_type(type_oid) }, contained_oid)
+        }
+        _ if unsafe { pg_sys::type_is_rowtype(type_oid) } => unsafe {
+            let tuple_desc = pg_sys::lookup_rowtype_tupdesc(type_oid, -1);
+            let contains = (*tuple_desc)
+                .attrs
+                .as_slice((*tuple_desc).natts as usize)
+                .iter()
+                .filter(|attr| !attr.attisdropped)
+                .any(|attr| type_contains(attr.atttypid, contained_oid));
+            pg_sys::DecrTupleDescRefCount(tuple_desc);
+            contains
+        },
+        _ => false,
+    }
+}
+
+pub fn type_name(type_oid: Oid) -> String {
+    unsafe { std::ffi::CStr::from_ptr(pg_sys::format_type_be(type_oid)) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+// How the keys of an index column are ordered, based on its DESC and NULLS FIRST/LAST options. Ascending columns
+// store their elements as they are, so the default ordering keeps the same keys as before these options were
+// supported. Descending columns wrap the packed element in a byte string with its bytes inverted, which reverses
+// their order. NULLs are then either stored as `Nil` to sort before all other elements, or as `null_element()` to
+// sort after them.
+#[derive(Clone, Copy)]
+pub struct ColumnOrder {
+    pub descending: bool,
+    pub nulls_first: bool,
+}
+
+impl ColumnOrder {
+    pub fn from_index_option(option: i16) -> Self {
+        ColumnOrder {
+            descending: option as u32 & pg_sys::INDOPTION_DESC != 0,
+            nulls_first: option as u32 & pg_sys::INDOPTION_NULLS_FIRST != 0,
+        }
+    }
+
+    pub fn encode<'a>(&self, element: Element<'a>) -> Element<'a> {
+        if !self.descending {
+            return element;
+        }
+
+        // An element can be a prefix of a larger element, in which case the larger element continues with 0xFF
+        // (see the escaping of zero bytes in strings). After inverting, that byte is 0x00, so we end with 0xFF to
+        // make the smaller element sort last.
+        let packed = foundationdb::tuple::pack(&element);
+        let mut inverted: Vec<u8> = packed.iter().map(|byte| !byte).collect();
+        inverted.push(0xFF);
+        Element::Bytes(inverted.into())
+    }
+
+    pub fn null<'a>(&self) -> Element<'a> {
+        if self.nulls_first {
+            Element::Nil
+        } else {
+            null_element()
+        }
+    }
+}
+
+// Postgres compares text using the collation of the column, which for most collations doesn't match the UTF-8 byte
+// order that FDB uses for strings. For those we instead store a sort key from the collation provider (`strxfrm` for
+// libc and `ucol_getSortKey` for ICU), which sorts bytewise in collation order. The "C" collation compares bytes, so
+// those values are stored as plain strings.
+//
+// Deterministic collations break ties between strings which the collation considers equal by comparing their bytes,
+// so for those the original value is appended after the sort key, separated by a zero byte which sorts before any
+// byte of a sort key. Rows are always rechecked against the original value in the table.
+fn encode_text<'a>(text: &str, collation: Oid) -> Element<'a> {
+    if collation == pg_sys::InvalidOid || unsafe { pg_sys::lc_collate_is_c(collation) } {
+        return Element::String(Cow::Owned(text.to_string()));
+    }
+
+    let locale = unsafe { pg_sys::pg_newlocale_from_collation(collation) };
+    let mut key = collation_sort_key(text, locale);
+
+    // A null locale is the libc default collation, which is always deterministic
+    let deterministic = locale.is_null() || unsafe { (*locale).deterministic };
+    if deterministic {
+        key.push(0x00);
+        key.extend_from_slice(text.as_bytes());
+    }
+
+    Element::Bytes(key.into())
+}
+
+fn collation_sort_key(text: &str, locale: pg_sys::pg_locale_t) -> Vec<u8> {
+    let mut key = vec![0u8; text.len() * 2 + 1];
+
+    loop {
+        let length = unsafe {
+            pg_sys::pg_strnxfrm(
+                key.as_mut_ptr() as *mut std::ffi::c_char,
+                key.len(),
+                text.as_ptr() as *const std::ffi::c_char,
+                text.len(),
+                locale,
+            )
+        };
+
+        // The key didn't fit in the buffer, so we retry with the size that was reported
+        if length >= key.len() {
+            key.resize(length + 1, 0);
+            continue;
+        }
+
+        key.truncate(length);
+        break;
+    }
+
+    // ICU includes the terminating zero byte in its sort keys, which would break the tie-breaking separator
+    while key.last() == Some(&0x00) {
+        key.pop();
+    }
+
+    key
+}
+
+// Enums are compared by the sort order of their values in `pg_enum`, so we encode that rather than the OID of the
+// value. The sort order of existing values usually doesn't change, but when a value is added with `ALTER TYPE ...
+// ADD VALUE BEFORE/AFTER` and there's no room left between its neighbours, Postgres renumbers all values of the enum.
+// The keys of existing index entries then no longer match, so indexes on the enum have to be rebuilt with REINDEX.
+fn encode_enum<'a>(datum: Datum) -> Element<'a> {
+    unsafe {
+        let tuple = pg_sys::SearchSysCache1(pg_sys::SysCacheIdentifier::ENUMOID as i32, datum);
+        if tuple.is_null() {
+            ereport!(
+                ERROR,
+                PgSqlErrorCode::ERRCODE_INTERNAL_ERROR,
+                &format!("cache lookup failed for enum value {}", datum.value())
             );
         }
+
+        let header = (*tuple).t_data;
+        let form =
+            (header as *mut u8).add((*header).t_hoff as usize) as *mut pg_sys::FormData_pg_enum;
+        let sort_order = (*form).enumsortorder as f64;
+        pg_sys::ReleaseSysCache(tuple);
+
+        Element::Double(sort_order)
+    }
+}
+
+// Encodes an element of an array or a field of a composite. Postgres sorts NULLs after all other values in both, but
+// FDB sorts `Nil` first, so each value is prefixed with a flag which is only set for NULLs.
+fn push_nullable_element<'a>(
+    elements: &mut Vec<Element<'a>>,
+    datum: Datum,
+    is_null: bool,
+    type_oid: Oid,
+    collation: Oid,
+) {
+    elements.push(Element::Bool(is_null));
+    if !is_null {
+        elements.push(encode_datum_for_index(datum, type_oid, collation));
     }
 }
+
+// Postgres compares arrays element by element, then by the number of elements and finally by their dimensions
+// (see `array_cmp`). We encode the elements as a nested tuple, where a shorter tuple sorts before a longer one with
+// the same prefix, followed by the number of dimensions, the size of each dimension and then the lower bound of
+// each dimension, as all sizes are compared before any lower bound.
+fn encode_array<'a>(datum: Datum, collation: Oid) -> Element<'a> {
+    unsafe {
+        let array = pg_sys::pg_detoast_datum(datum.cast_mut_ptr()) as *mut pg_sys::ArrayType;
+        let ndim = (*array).ndim as usize;
+        let (element_type, values) = deconstruct_array(array);
+
+        let mut elements = Vec::with_capacity(values.len() * 2);
+        for value in values {
+            push_nullable_element(
+                &mut elements,
+                value.unwrap_or(Datum::from(0)),
+                value.is_none(),
+                element_type,
+                collation,
+            );
+        }
+
+        // The dimensions and lower bounds are stored right after the array header (see `ARR_DIMS` and `ARR_LBOUND`)
+        let dims = (array as *const u8).add(std::mem::size_of::<pg_sys::ArrayType>()) as *const i32;
+        let lower_bounds = dims.add(ndim);
+
+        let mut encoded = vec![Element::Tuple(elements), Element::Int(ndim as i64)];
+        for i in 0..ndim {
+            encoded.push(Element::Int(*dims.add(i) as i64));
+        }
+        for i in 0..ndim {
+            encoded.push(Element::Int(*lower_bounds.add(i) as i64));
+        }
+
+        Element::Tuple(encoded)
+    }
+}
+
+// Returns the element type and the elements of an array datum, with None for NULL elements
+pub fn deconstruct_array_datum(datum: Datum) -> (Oid, Vec<Option<Datum>>) {
+    unsafe {
+        let array = pg_sys::pg_detoast_datum(datum.cast_mut_ptr()) as *mut pg_sys::ArrayType;
+        deconstruct_array(array)
+    }
+}
+
+unsafe fn deconstruct_array(array: *mut pg_sys::ArrayType) -> (Oid, Vec<Option<Datum>>) {
+    unsafe {
+        let element_type = (*array).elemtype;
+
+        let mut element_length = 0;
+        let mut element_by_value = false;
+        let mut element_alignment = 0;
+        pg_sys::get_typlenbyvalalign(
+            element_type,
+            &mut element_length,
+            &mut element_by_value,
+            &mut element_alignment,
+        );
+
+        let mut values = std::ptr::null_mut();
+        let mut nulls = std::ptr::null_mut();
+        let mut count = 0;
+        pg_sys::deconstruct_array(
+            array,
+            element_type,
+            element_length.into(),
+            element_by_value,
+            element_alignment,
+            &mut values,
+            &mut nulls,
+            &mut count,
+        );
+
+        let elements = (0..count as usize)
+            .map(|i| (!*nulls.add(i)).then(|| *values.add(i)))
+            .collect();
+
+        (element_type, elements)
+    }
+}
+
+// Postgres compares composites field by field, skipping dropped columns (see `record_cmp`). We encode the fields as
+// a nested tuple, using the collation of each field.
+fn encode_composite<'a>(datum: Datum) -> Element<'a> {
+    unsafe {
+        let header = pg_sys::pg_detoast_datum(datum.cast_mut_ptr()) as pg_sys::HeapTupleHeader;
+        let type_oid = (*header).t_choice.t_datum.datum_typeid;
+        let type_mod = (*header).t_choice.t_datum.datum_typmod;
+        let tuple_desc = pg_sys::lookup_rowtype_tupdesc(type_oid, type_mod);
+        let natts = (*tuple_desc).natts as usize;
+
+        // Build a temporary heap tuple around the composite so that it can be deformed
+        let mut tuple = pg_sys::HeapTupleData {
+            t_len: pgrx::varlena::varsize_any(header as *const pg_sys::varlena) as u32,
+            t_self: pg_sys::ItemPointerData::default(),
+            t_tableOid: pg_sys::InvalidOid,
+            t_data: header,
+        };
+
+        let mut values = vec![Datum::from(0); natts];
+        let mut nulls = vec![false; natts];
+        pg_sys::heap_deform_tuple(
+            &mut tuple,
+            tuple_desc,
+            values.as_mut_ptr(),
+            nulls.as_mut_ptr(),
+        );
+
+        let attrs = (*tuple_desc).attrs.as_slice(natts);
+        let mut elements = Vec::with_capacity(natts * 2);
+        for (i, attr) in attrs.iter().enumerate() {
+            if attr.attisdropped {
+                continue;
+            }
+
+            push_nullable_element(
+                &mut elements,
+                values[i],
+                nulls[i],
+                attr.atttypid,
+                attr.attcollation,
+            );
+        }
+
+        pg_sys::DecrTupleDescRefCount(tuple_desc);
+
+        Element::Tuple(elements)
+    }
+}
+
+// Postgres considers -0 and 0 equal and all NaNs equal and greater than any other value. FDB orders doubles
+// by their bits, so we map each of those groups to a single representation to make the key order match.
+// The positive NaN we map to already sorts after positive infinity in FDB.
+fn normalize_float(value: f64) -> f64 {
+    if value.is_nan() {
+        f64::NAN
+    } else if value == 0.0 {
+        0.0
+    } else {
+        value
+    }
+}
+
+// Postgres compares intervals by converting them to a single 128-bit number of microseconds, treating
+// a month as 30 days and a day as 24 hours (see `interval_cmp_value`). That means '1 month' and '30 days'
+// are equal, so we encode that value rather than the separate fields. It doesn't fit in an FDB integer,
+// so we store it as big-endian bytes with the sign bit flipped, which makes byte order match numeric order.
+fn encode_interval(interval: &pg_sys::Interval) -> [u8; 16] {
+    const USECS_PER_DAY: i128 = 86_400_000_000;
+
+    let days = interval.month as i128 * 30 + interval.day as i128;
+    let value = days * USECS_PER_DAY + interval.time as i128;
+    (value ^ i128::MIN).to_be_bytes()
+}
+
+// Encodes the text representation of a numeric into bytes which sort in the same order as Postgres compares
+// numerics. The first byte is the class of the value (negative infinity, negative, zero, positive, positive
+// infinity or NaN), which makes NaN sort above all other values like in Postgres.
+//
+// Finite non-zero values are written as 0.d1d2d3... * 10^exponent with the exponent encoded as a sign-flipped
+// big-endian integer, followed by the significant decimal digits. Leading and trailing zeros are dropped so that
+// values which are equal but have different scales, like 1.0 and 1.00, get the same encoding. For negative values
+// the exponent and digits are inverted and a terminator is added, so that larger magnitudes sort first.
+fn encode_numeric(numeric: &str) -> Vec<u8> {
+    const NEGATIVE_INFINITY: u8 = 0x00;
+    const NEGATIVE: u8 = 0x01;
+    const ZERO: u8 = 0x02;
+    const POSITIVE: u8 = 0x03;
+    const POSITIVE_INFINITY: u8 = 0x04;
+    const NAN: u8 = 0x05;
+
+    match numeric {
+        "NaN" => return vec![NAN],
+        "Infinity" => return vec![POSITIVE_INFINITY],
+        "-Infinity" => return vec![NEGATIVE_INFINITY],
+        _ => {}
+    }
+
+    let (negative, unsigned) = match numeric.strip_prefix('-') {
+        Some(unsigned) => (true, unsigned),
+        None => (false, numeric),
+    };
+    let (integer, fraction) = unsigned.split_once('.').unwrap_or((unsigned, ""));
+
+    let digits: Vec<u8> = integer
+        .bytes()
+        .chain(fraction.bytes())
+        .map(|digit| digit - b'0')
+        .collect();
+
+    let Some(first) = digits.iter().position(|digit| *digit != 0) else {
+        return vec![ZERO];
+    };
+    let last = digits.iter().rposition(|digit| *digit != 0).unwrap();
+
+    // The exponent is the number of digits before the decimal point, minus any leading zeros
+    let exponent = integer.len() as i32 - first as i32;
+    let significant = &digits[first..=last];
+
+    let mut encoded = Vec::with_capacity(significant.len() + 6);
+    if negative {
+        encoded.push(NEGATIVE);
+        encoded.extend_from_slice(&((-exponent) as u32 ^ 0x8000_0000).to_be_bytes());
+        encoded.extend(significant.iter().map(|digit| 9 - digit));
+        // Ensures that a shorter sequence of digits (smaller magnitude) sorts after a longer one with the same prefix
+        encoded.push(0xFF);
+    } else {
+        encoded.push(POSITIVE);
+        encoded.extend_from_slice(&(exponent as u32 ^ 0x8000_0000).to_be_bytes());
+        encoded.extend_from_slice(significant);
+    }
+
+    encoded
+}
@@ -0,0 +1,168 @@
+use std::ffi::c_void;
+
+use futures::future::try_join_all;
+use pg_sys::{
+    BufferAccessStrategy, IndexBulkDeleteCallback, IndexBulkDeleteResult, IndexVacuumInfo,
+    ItemPointerData, Relation,
+};
+use pgrx::{itemptr::item_pointer_set_all, prelude::*};
+use pollster::FutureExt;
+
+use crate::{iam::build::row_id_from_index_key, transaction::run_in_batches};
+
+// Number of index entries checked per transaction when vacuuming
+const VACUUM_BATCH_SIZE: usize = 1000;
+
+// Vacuums all pgfdb indexes on a table. This is called by `relation_vacuum` in the TAM, as table access methods
+// are responsible for vacuuming their indexes. Results are reported at the given log level, which is INFO for
+// `VACUUM VERBOSE`.
+pub unsafe fn vacuum_indexes(
+    table_rel: Relation,
+    log_level: PgLogLevel,
+    strategy: BufferAccessStrategy,
+) {
+    unsafe {
+        let am_oid = pg_sys::get_index_am_oid(c"pgfdb".as_ptr(), true);
+
+        let mut num_indexes = 0;
+        let mut indexes: *mut Relation = std::ptr::null_mut();
+        pg_sys::vac_open_indexes(
+            table_rel,
+            pg_sys::RowExclusiveLock as pg_sys::LOCKMODE,
+            &mut num_indexes,
+            &mut indexes,
+        );
+
+        for &index_rel in std::slice::from_raw_parts(indexes, num_indexes as usize) {
+            if (*(*index_rel).rd_rel).relam != am_oid {
+                continue;
+            }
+
+            let mut info = PgBox::<IndexVacuumInfo>::alloc0();
+            info.index = index_rel;
+            info.heaprel = table_rel;
+            info.message_level = log_level as i32;
+            info.num_heap_tuples = -1.0;
+            info.estimated_count = true;
+            info.strategy = strategy;
+
+            // Entries are only removed because their row is gone, so there is no need for a callback
+            let stats = pg_sys::index_bulk_delete(
+                info.as_ptr(),
+                std::ptr::null_mut(),
+                None,
+                std::ptr::null_mut(),
+            );
+            let stats = pg_sys::index_vacuum_cleanup(info.as_ptr(), stats);
+            if stats.is_null() {
+                continue;
+            }
+
+            let index_name = pgrx::name_data_to_str(&(*(*index_rel).rd_rel).relname);
+            ereport!(
+                log_level,
+                PgSqlErrorCode::ERRCODE_SUCCESSFUL_COMPLETION,
+                &format!(
+                    "index \"{index_name}\" now contains {} entries, {} dangling entries were removed",
+                    (*stats).num_index_tuples,
+                    (*stats).tuples_removed
+                )
+            );
+        }
+
+        pg_sys::vac_close_indexes(num_indexes, indexes, pg_sys::NoLock as pg_sys::LOCKMODE);
+    }
+}
+
+// Bulk delete index entries. Deleting a row clears its index entries in the same transaction, but entries can
+// still be left behind pointing to rows which no longer exist. Scans skip those, at the cost of a wasted read, so
+// here we walk the index in batches and clear them. Entries are also cleared if the callback reports their row as
+// dead. This is also called by `CREATE INDEX CONCURRENTLY` to collect the entries of the index before validating it,
+// which our validation doesn't need (see `index_validate_scan` in the TAM).
+pub unsafe extern "C-unwind" fn ambulkdelete(
+    info: *mut IndexVacuumInfo,
+    stats: *mut IndexBulkDeleteResult,
+    callback: IndexBulkDeleteCallback,
+    callback_state: *mut c_void,
+) -> *mut IndexBulkDeleteResult {
+    log!("IAM: Bulk delete");
+
+    unsafe {
+        let stats = if stats.is_null() {
+            PgBox::<IndexBulkDeleteResult>::alloc0().into_pg()
+        } else {
+            stats
+        };
+
+        let table_oid = (*(*info).heaprel).rd_id;
+        let table_subspace = crate::subspace::table(table_oid);
+        let index_subspace = crate::subspace::index((*(*info).index).rd_locator.relNumber);
+
+        let batches = run_in_batches(
+            table_oid,
+            index_subspace.range(),
+            VACUUM_BATCH_SIZE,
+            |txn, entries| {
+                let row_ids: Vec<Option<u32>> = entries
+                    .iter()
+                    .map(|entry| row_id_from_index_key(entry.key()))
+                    .collect();
+
+                // The rows are read without snapshot isolation, so that clearing an entry conflicts with a
+                // concurrent transaction writing its row
+                let rows = try_join_all(row_ids.iter().map(|id| async {
+                    match id {
+                        Some(id) => txn.get(&table_subspace.pack(id), false).await,
+                        None => Ok(None),
+                    }
+                }))
+                .block_on()?;
+
+                let mut num_removed = 0.0;
+                let mut num_remaining = 0.0;
+                for ((entry, id), row) in entries.iter().zip(row_ids).zip(rows) {
+                    let dead = match (id, row, callback) {
+                        (Some(id), Some(_), Some(callback)) => {
+                            let mut tid = ItemPointerData::default();
+                            item_pointer_set_all(&mut tid, id, 1);
+                            callback(&mut tid, callback_state)
+                        }
+                        (Some(_), Some(_), None) => false,
+                        _ => true,
+                    };
+
+                    if dead {
+                        txn.clear(entry.key());
+                        num_removed += 1.0;
+                    } else {
+                        num_remaining += 1.0;
+                    }
+                }
+                Ok((num_removed, num_remaining))
+            },
+        );
+
+        for (num_removed, num_remaining) in batches {
+            (*stats).tuples_removed += num_removed;
+            (*stats).num_index_tuples += num_remaining;
+        }
+        stats
+    }
+}
+
+// Cleanup after vacuuming an index. The counts were collected by `ambulkdelete`, and as our indexes aren't stored
+// in pages, there are no pages to report. If `ambulkdelete` wasn't called, there is nothing to report either.
+pub unsafe extern "C-unwind" fn amvacuumcleanup(
+    _info: *mut IndexVacuumInfo,
+    stats: *mut IndexBulkDeleteResult,
+) -> *mut IndexBulkDeleteResult {
+    log!("IAM: Vacuum cleanup");
+
+    if !stats.is_null() {
+        unsafe {
+            (*stats).num_pages = 0;
+            (*stats).estimated_count = false;
+        }
+    }
+    stats
+}
@@ -0,0 +1,207 @@
+use std::ffi::CStr;
+
+use pg_sys::{FormData_pg_amop, FormData_pg_amproc, FormData_pg_opclass, HeapTuple, Oid};
+use pgrx::prelude::*;
+
+use crate::iam::utils::{is_supported_type, type_name};
+
+// Validates one of our operator classes, like `btvalidate` does for btree. Problems are reported as INFO messages
+// and make the validation fail. This runs for all of our operator families when the extension is created (see
+// below), and can be run manually with `amvalidate(opclass)`.
+pub unsafe extern "C-unwind" fn amvalidate(opclass_oid: Oid) -> bool {
+    unsafe {
+        let tuple = pg_sys::SearchSysCache1(
+            pg_sys::SysCacheIdentifier::CLAOID as i32,
+            opclass_oid.into_datum().unwrap(),
+        );
+        if tuple.is_null() {
+            error!(
+                "cache lookup failed for operator class {}",
+                opclass_oid.to_u32()
+            );
+        }
+        let opclass = &*get_struct::<FormData_pg_opclass>(tuple);
+        let opfamily = opclass.opcfamily;
+        let input_type = opclass.opcintype;
+        let opclass_name = pgrx::name_data_to_str(&opclass.opcname).to_string();
+        pg_sys::ReleaseSysCache(tuple);
+
+        let mut valid = true;
+        let mut report = |message: String| {
+            ereport!(
+                PgLogLevel::INFO,
+                PgSqlErrorCode::ERRCODE_INVALID_OBJECT_DEFINITION,
+                &format!("operator class \"{opclass_name}\" of access method pgfdb {message}")
+            );
+            valid = false;
+        };
+
+        // Every type the operator class works on must have an encoding for index keys. Polymorphic types can only
+        // be checked once we know the actual type of an indexed column, which is done when the index is built.
+        if !is_supported_type_or_polymorphic(input_type) {
+            report(format!(
+                "has unsupported input type {}",
+                type_name(input_type)
+            ));
+        }
+
+        let mut has_equality = false;
+        for_each_in_sys_cache_list(
+            pg_sys::SysCacheIdentifier::AMOPSTRATEGY,
+            opfamily,
+            |tuple| {
+                let operator = &*get_struct::<FormData_pg_amop>(tuple);
+                let name = operator_name(operator.amopopr);
+
+                if operator.amoppurpose as u8 != pg_sys::AMOP_SEARCH as u8 {
+                    report(format!(
+                        "contains ordering operator {name}, which isn't supported"
+                    ));
+                    return;
+                }
+
+                if !(1..=6).contains(&operator.amopstrategy) {
+                    report(format!(
+                        "contains operator {name} with invalid strategy number {}",
+                        operator.amopstrategy
+                    ));
+                }
+
+                for operand_type in [operator.amoplefttype, operator.amoprighttype] {
+                    if !is_supported_type_or_polymorphic(operand_type) {
+                        report(format!(
+                            "contains operator {name} with unsupported type {}",
+                            type_name(operand_type)
+                        ));
+                    }
+                }
+
+                if pg_sys::get_op_rettype(operator.amopopr) != pg_sys::BOOLOID {
+                    report(format!(
+                        "contains operator {name} which doesn't return boolean"
+                    ));
+                }
+
+                has_equality |= operator.amopstrategy == 3
+                    && operator.amoplefttype == input_type
+                    && operator.amoprighttype == input_type;
+            },
+        );
+
+        // Only the comparison function for the input type is required, cross-type operators don't need one as we
+        // compare the encoded keys instead
+        let mut has_comparison = false;
+        for_each_in_sys_cache_list(pg_sys::SysCacheIdentifier::AMPROCNUM, opfamily, |tuple| {
+            let procedure = &*get_struct::<FormData_pg_amproc>(tuple);
+            let name = procedure_name(procedure.amproc);
+
+            if procedure.amprocnum != 1 {
+                report(format!(
+                    "contains function {name} with invalid support number {}",
+                    procedure.amprocnum
+                ));
+                return;
+            }
+
+            let mut arg_types: *mut Oid = std::ptr::null_mut();
+            let mut nargs = 0;
+            let return_type =
+                pg_sys::get_func_signature(procedure.amproc, &mut arg_types, &mut nargs);
+            let arg_types = if nargs > 0 {
+                std::slice::from_raw_parts(arg_types, nargs as usize)
+            } else {
+                &[]
+            };
+            let matches_types = arg_types.len() == 2
+                && pg_sys::IsBinaryCoercible(procedure.amproclefttype, arg_types[0])
+                && pg_sys::IsBinaryCoercible(procedure.amprocrighttype, arg_types[1]);
+            if return_type != pg_sys::INT4OID || !matches_types {
+                report(format!("contains function {name} with wrong signature"));
+            }
+
+            has_comparison |=
+                procedure.amproclefttype == input_type && procedure.amprocrighttype == input_type;
+        });
+
+        if !has_equality {
+            report(format!(
+                "is missing an equality operator for type {}",
+                type_name(input_type)
+            ));
+        }
+        if !has_comparison {
+            report(format!(
+                "is missing a comparison function for type {}",
+                type_name(input_type)
+            ));
+        }
+
+        valid
+    }
+}
+
+// Make sure all of our operator classes are consistent once they have been created, problems are reported by
+// amvalidate
+extension_sql!(
+    "
+    DO $$
+    BEGIN
+        IF NOT (
+            SELECT bool_and(amvalidate(opc.oid))
+            FROM pg_opclass opc
+            JOIN pg_am am ON am.oid = opc.opcmethod
+            WHERE am.amname = 'pgfdb'
+        ) THEN
+            RAISE EXCEPTION 'pgfdb operator classes failed validation';
+        END IF;
+    END
+    $$;
+    ",
+    name = "pgfdb_validate_operator_classes",
+    finalize,
+);
+
+fn is_supported_type_or_polymorphic(type_oid: Oid) -> bool {
+    matches!(type_oid, pg_sys::ANYARRAYOID | pg_sys::ANYENUMOID) || is_supported_type(type_oid)
+}
+
+// Calls a function for each tuple of a catalog cache list, looked up by a single key
+pub(super) unsafe fn for_each_in_sys_cache_list(
+    cache: pg_sys::SysCacheIdentifier::Type,
+    key: Oid,
+    mut f: impl FnMut(HeapTuple),
+) {
+    unsafe {
+        let list = pg_sys::SearchSysCacheList(
+            cache as i32,
+            1,
+            key.into_datum().unwrap(),
+            0.into(),
+            0.into(),
+        );
+        for member in (*list).members.as_slice((*list).n_members as usize) {
+            f(&mut (**member).tuple);
+        }
+        pg_sys::ReleaseCatCacheList(list);
+    }
+}
+
+// Returns the struct of a catalog tuple, like the `GETSTRUCT` macro
+pub(super) unsafe fn get_struct<T>(tuple: HeapTuple) -> *mut T {
+    unsafe {
+        let header = (*tuple).t_data;
+        (header as *mut u8).add((*header).t_hoff as usize) as *mut T
+    }
+}
+
+fn operator_name(operator_oid: Oid) -> String {
+    unsafe { CStr::from_ptr(pg_sys::format_operator(operator_oid)) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+fn procedure_name(procedure_oid: Oid) -> String {
+    unsafe { CStr::from_ptr(pg_sys::format_procedure(procedure_oid)) }
+        .to_string_lossy()
+        .into_owned()
+}
@@ -0,0 +1,168 @@
+use futures::future::try_join_all;
+use pg_sys::{IndexInfo, Relation, TupleTableSlot};
+use pgrx::{PgRelation, prelude::*};
+use pollster::FutureExt;
+
+use crate::{
+    iam::build::{build_key_from_table_tuple, row_id_from_index_key},
+    transaction::run_in_batches,
+};
+
+// Number of index entries or table rows checked per transaction, which keeps each batch well below FDB's 5 second
+// transaction limit
+const VERIFY_BATCH_SIZE: usize = 1000;
+
+// Problems found by `pgfdb_verify_index`, with the row they belong to and the index key in question
+type Problem = (String, Option<i64>, Vec<u8>);
+
+// Checks that a pgfdb index is consistent with its table, like `bt_index_check` from amcheck. Every index entry
+// must point to an existing row which produces the same key. Entries pointing to deleted rows are reported as
+// "extra" and entries with outdated values as "mismatched". With `heapallindexed`, the table is scanned as well and
+// rows without an index entry are reported as "missing". Can be run with `SELECT * FROM pgfdb_verify_index('idx')`.
+#[pg_extern]
+fn pgfdb_verify_index(
+    index: PgRelation,
+    heapallindexed: default!(bool, false),
+) -> TableIterator<
+    'static,
+    (
+        name!(kind, String),
+        name!(row_id, Option<i64>),
+        name!(index_key, Vec<u8>),
+    ),
+> {
+    let am_oid = unsafe { pg_sys::get_index_am_oid(c"pgfdb".as_ptr(), true) };
+    if !index.is_index() || unsafe { (*index.rd_rel).relam } != am_oid {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_WRONG_OBJECT_TYPE,
+            &format!("\"{}\" is not a pgfdb index", index.name())
+        );
+    }
+    let table = index.heap_relation().unwrap();
+
+    let mut problems = unsafe {
+        let index_info = pg_sys::BuildIndexInfo(index.as_ptr());
+        let heap_slot =
+            pg_sys::MakeSingleTupleTableSlot((*table.as_ptr()).rd_att, &pg_sys::TTSOpsVirtual);
+
+        let mut problems =
+            verify_index_entries(table.as_ptr(), index.as_ptr(), index_info, heap_slot);
+        if heapallindexed {
+            problems.extend(verify_table_rows(
+                table.as_ptr(),
+                index.as_ptr(),
+                index_info,
+                heap_slot,
+            ));
+        }
+
+        pg_sys::ExecDropSingleTupleTableSlot(heap_slot);
+        problems
+    };
+
+    problems.sort_by(|a, b| a.1.cmp(&b.1));
+    TableIterator::new(problems)
+}
+
+// Walks the index and checks that every entry points to a row which produces the same key
+unsafe fn verify_index_entries(
+    table_rel: Relation,
+    index_rel: Relation,
+    index_info: *mut IndexInfo,
+    heap_slot: *mut TupleTableSlot,
+) -> Vec<Problem> {
+    unsafe {
+        let table_oid = (*table_rel).rd_id;
+        let table_subspace = crate::subspace::table(table_oid);
+        let index_subspace = crate::subspace::index((*index_rel).rd_locator.relNumber);
+
+        let batches = run_in_batches(
+            table_oid,
+            index_subspace.range(),
+            VERIFY_BATCH_SIZE,
+            |txn, entries| {
+                let row_ids: Vec<Option<u32>> = entries
+                    .iter()
+                    .map(|entry| row_id_from_index_key(entry.key()))
+                    .collect();
+
+                let rows = try_join_all(row_ids.iter().map(|id| async {
+                    match id {
+                        Some(id) => txn.get(&table_subspace.pack(id), false).await,
+                        None => Ok(None),
+                    }
+                }))
+                .block_on()?;
+
+                let mut problems = Vec::new();
+                for ((entry, id), row) in entries.iter().zip(row_ids).zip(rows) {
+                    let key = entry.key().to_vec();
+                    let (Some(id), Some(row)) = (id, row) else {
+                        problems.push(("extra".to_string(), id.map(i64::from), key));
+                        continue;
+                    };
+
+                    let mut tuple = crate::coding::Tuple::deserialize(&row);
+                    tuple.load_into_tts(heap_slot.as_mut().unwrap());
+                    let expected_key =
+                        build_key_from_table_tuple(id, index_rel, heap_slot, index_info);
+
+                    match expected_key {
+                        Some(expected_key) if expected_key == key => {}
+                        // Rows excluded by the predicate of a partial index shouldn't have an entry at all
+                        None => problems.push(("extra".to_string(), Some(id.into()), key)),
+                        Some(_) => problems.push(("mismatched".to_string(), Some(id.into()), key)),
+                    }
+                }
+                Ok(problems)
+            },
+        );
+        batches.into_iter().flatten().collect()
+    }
+}
+
+// Walks the table and checks that every row has an entry in the index
+unsafe fn verify_table_rows(
+    table_rel: Relation,
+    index_rel: Relation,
+    index_info: *mut IndexInfo,
+    heap_slot: *mut TupleTableSlot,
+) -> Vec<Problem> {
+    unsafe {
+        let table_oid = (*table_rel).rd_id;
+        let table_subspace = crate::subspace::table(table_oid);
+
+        let batches = run_in_batches(
+            table_oid,
+            table_subspace.range(),
+            VERIFY_BATCH_SIZE,
+            |txn, rows| {
+                let mut expected_keys = Vec::new();
+                for row in rows.iter() {
+                    let mut tuple = crate::coding::Tuple::deserialize(row.value());
+                    let id = tuple.id;
+                    tuple.load_into_tts(heap_slot.as_mut().unwrap());
+
+                    if let Some(key) =
+                        build_key_from_table_tuple(id, index_rel, heap_slot, index_info)
+                    {
+                        expected_keys.push((id, key));
+                    }
+                }
+
+                let entries =
+                    try_join_all(expected_keys.iter().map(|(_, key)| txn.get(key, false)))
+                        .block_on()?;
+
+                Ok(expected_keys
+                    .into_iter()
+                    .zip(entries)
+                    .filter(|(_, entry)| entry.is_none())
+                    .map(|((id, key), _)| ("missing".to_string(), Some(id.into()), key))
+                    .collect::<Vec<Problem>>())
+            },
+        );
+        batches.into_iter().flatten().collect()
+    }
+}
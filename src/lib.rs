@@ -21,6 +21,10 @@ pub extern "C-unwind" fn _PG_init() {
     unsafe { env::set_var("RUST_BACKTRACE", "1") };
 
     fdb::init();
+    iam::state::init();
+    iam::enums::init();
+    iam::scan::init();
+    iam::join::init();
 
     unsafe {
         RegisterXactCallback(
@@ -210,6 +214,534 @@ mod tests {
         assert_eq!(Some(0), result);
     }
 
+    #[pg_test]
+    fn select_with_and_without_mapped_ranges() {
+        Spi::run("CREATE TABLE test (id INTEGER, category INTEGER) USING pgfdb_table").unwrap();
+        Spi::run("CREATE INDEX category_idx ON test USING pgfdb(category)").unwrap();
+        Spi::run("INSERT INTO test SELECT i, i % 10 FROM generate_series(1, 2000) AS i").unwrap();
+        Spi::run("SET enable_seqscan=0").unwrap();
+
+        // Mapped ranges can't read our own writes, so both settings must fall back to looking up the rows
+        // separately, including for the rows updated while the scan of the UPDATE is running
+        for setting in ["on", "off"] {
+            Spi::run(&format!("SET pgfdb.mapped_ranges={setting}")).unwrap();
+            Spi::run("UPDATE test SET id = id + 10000 WHERE category = 3").unwrap();
+
+            let result: Option<i64> =
+                Spi::get_one("SELECT sum(id) FROM test WHERE category = 3").unwrap();
+            let expected = (1..=2000)
+                .filter(|i| i % 10 == 3)
+                .map(|i| i + if setting == "on" { 10000 } else { 20000 })
+                .sum::<i64>();
+            assert_eq!(Some(expected), result, "mapped_ranges={setting}");
+        }
+    }
+
+    #[pg_test]
+    fn select_committed_rows_with_mapped_ranges() {
+        Spi::run("CREATE TABLE test (id INTEGER, category INTEGER, name TEXT) USING pgfdb_table")
+            .unwrap();
+        Spi::run("CREATE INDEX category_idx ON test USING pgfdb(category)").unwrap();
+        Spi::run(
+            "INSERT INTO test SELECT i, i % 10, 'name' || i FROM generate_series(1, 2000) AS i",
+        )
+        .unwrap();
+        // Mapped ranges are only used when the transaction hasn't written anything
+        commit_session_transaction();
+        Spi::run("SET enable_seqscan=0").unwrap();
+
+        let queries = [
+            "SELECT string_agg(id || ':' || name, ',' ORDER BY id) FROM test WHERE category = 3",
+            "SELECT string_agg(id || ':' || name, ',' ORDER BY id) FROM test WHERE category IN (1, 7)",
+            "SELECT string_agg(id::text, ',') FROM (SELECT id FROM test WHERE category >= 8 ORDER BY category DESC, id) AS sorted",
+        ];
+        for query in queries {
+            let explain = Spi::explain(query).unwrap();
+            assert!(
+                format!("{:?}", explain).contains("Index Name"),
+                "expected query plan to use index: {:?}",
+                explain.0.to_string()
+            );
+
+            Spi::run("SET pgfdb.mapped_ranges=on").unwrap();
+            let mapped: Option<String> = Spi::get_one(query).unwrap();
+            Spi::run("SET pgfdb.mapped_ranges=off").unwrap();
+            let unmapped: Option<String> = Spi::get_one(query).unwrap();
+
+            assert!(mapped.is_some(), "no rows for {query}");
+            assert_eq!(unmapped, mapped, "unexpected rows for {query}");
+        }
+
+        clear_committed_table("test");
+    }
+
+    #[pg_test]
+    fn planner_picks_index_without_hints() {
+        Spi::run("CREATE TABLE test (id INTEGER, name TEXT) USING pgfdb_table").unwrap();
+        Spi::run("CREATE INDEX id_idx ON test USING pgfdb(id)").unwrap();
+        Spi::run(
+            "INSERT INTO test(id, name) SELECT i, 'name' || i FROM generate_series(1, 2000) AS i",
+        )
+        .unwrap();
+
+        // A selective condition should use the index
+        let query = "SELECT count(*) FROM test WHERE id = 42";
+        let explain = Spi::explain(query).unwrap();
+        assert!(
+            format!("{:?}", explain).contains("Index Name"),
+            "expected query plan to use index: {:?}",
+            explain.0.to_string()
+        );
+        let result: Option<i64> = Spi::get_one(query).unwrap();
+        assert_eq!(Some(1), result);
+
+        // While reading a large part of the table is cheaper without it
+        let query = "SELECT count(*) FROM test WHERE id > 1000";
+        let explain = Spi::explain(query).unwrap();
+        assert!(
+            format!("{:?}", explain).contains("Seq Scan"),
+            "expected query plan to use a sequential scan: {:?}",
+            explain.0.to_string()
+        );
+        let result: Option<i64> = Spi::get_one(query).unwrap();
+        assert_eq!(Some(1000), result);
+    }
+
+    #[pg_test]
+    fn planner_ignores_index_being_built() {
+        use crate::iam::state::{self, IndexState};
+
+        Spi::run("CREATE TABLE test (id INTEGER) USING pgfdb_table").unwrap();
+        Spi::run("CREATE INDEX id_idx ON test USING pgfdb(id)").unwrap();
+        Spi::run("INSERT INTO test(id) VALUES (1), (2), (3)").unwrap();
+        Spi::run("SET enable_seqscan=0").unwrap();
+
+        let index_oid: pg_sys::Oid = Spi::get_one("SELECT 'id_idx'::regclass::oid")
+            .unwrap()
+            .unwrap();
+        let txn = crate::transaction::get_transaction();
+        let query = "SELECT count(*) FROM test WHERE id = 2";
+
+        // The index is readable once built, but while write-only it must not be used for scans
+        assert_eq!(
+            Some(IndexState::Readable),
+            state::get(txn, index_oid, false).unwrap()
+        );
+        state::set(txn, index_oid, IndexState::WriteOnly { cursor: None });
+        let explain = Spi::explain(query).unwrap();
+        assert!(
+            !format!("{:?}", explain).contains("Index Name"),
+            "expected query plan to not use index: {:?}",
+            explain.0.to_string()
+        );
+
+        state::set(txn, index_oid, IndexState::Readable);
+        let explain = Spi::explain(query).unwrap();
+        assert!(
+            format!("{:?}", explain).contains("Index Name"),
+            "expected query plan to use index: {:?}",
+            explain.0.to_string()
+        );
+
+        let result: Option<i64> = Spi::get_one(query).unwrap();
+        assert_eq!(Some(1), result);
+    }
+
+    #[pg_test]
+    fn index_build_phase_names() {
+        let result: Option<Vec<String>> = Spi::get_one(
+            "SELECT array_agg(name ORDER BY phase) FROM (
+                SELECT phase, pg_indexam_progress_phasename(pg_am.oid, phase) AS name
+                FROM pg_am, generate_series(1, 4) AS phase WHERE amname = 'pgfdb'
+            ) AS phases WHERE name IS NOT NULL",
+        )
+        .unwrap();
+        assert_eq!(
+            Some(vec![
+                "scanning table".to_string(),
+                "writing keys".to_string(),
+                "validating index".to_string()
+            ]),
+            result
+        );
+    }
+
+    #[pg_test]
+    fn reindex_clears_stale_entries() {
+        use foundationdb::{RangeOption, options::StreamingMode};
+        use pollster::FutureExt;
+
+        Spi::run("CREATE TABLE test (id INTEGER) USING pgfdb_table").unwrap();
+        Spi::run("CREATE INDEX id_idx ON test USING pgfdb(id)").unwrap();
+        Spi::run("INSERT INTO test(id) VALUES (1), (2), (3)").unwrap();
+
+        let row_id: i64 =
+            Spi::get_one("SELECT (ctid::text::point)[0]::bigint FROM test WHERE id = 1")
+                .unwrap()
+                .unwrap();
+
+        // Rebuilding gives the index new storage, so the entries are looked up under its current relfilenumber
+        let txn = crate::transaction::get_transaction();
+        let index_subspace = || crate::subspace::index(index_relfilenumber("id_idx"));
+        let count_entries = || {
+            let range_option = RangeOption {
+                mode: StreamingMode::WantAll,
+                ..RangeOption::from(index_subspace().range())
+            };
+            txn.get_range(&range_option, 1, false)
+                .block_on()
+                .unwrap()
+                .len()
+        };
+
+        // Leave behind stale entries pointing to a row with values it doesn't have, which rebuilding should clear
+        for (reindex, stale_value) in [("REINDEX INDEX id_idx", 42), ("REINDEX TABLE test", 43)] {
+            txn.set(&index_subspace().pack(&(stale_value, row_id)), &[]);
+            assert_eq!(4, count_entries());
+
+            Spi::run(reindex).unwrap();
+            assert_eq!(
+                3,
+                count_entries(),
+                "unexpected index entries after {reindex}"
+            );
+        }
+
+        Spi::run("SET enable_seqscan=0").unwrap();
+        let result: Option<i64> = Spi::get_one("SELECT count(*) FROM test WHERE id >= 1").unwrap();
+        assert_eq!(Some(3), result);
+    }
+
+    #[pg_test]
+    fn reindex_replaces_entries_on_commit() {
+        use crate::iam::state::IndexState;
+
+        Spi::run("CREATE TABLE test (id INTEGER) USING pgfdb_table").unwrap();
+        Spi::run("INSERT INTO test(id) SELECT i FROM generate_series(1, 100) AS i").unwrap();
+        Spi::run("CREATE INDEX id_idx ON test USING pgfdb(id)").unwrap();
+        commit_session_transaction();
+        let old_relfilenumber = index_relfilenumber("id_idx");
+
+        // A REINDEX which fails leaves the old entries in place, and nothing in the new storage
+        Spi::run("REINDEX INDEX id_idx").unwrap();
+        let new_relfilenumber = index_relfilenumber("id_idx");
+        assert_ne!(old_relfilenumber, new_relfilenumber);
+        abort_session_transaction();
+        assert_eq!(
+            (100, Some(IndexState::Readable)),
+            committed_index_contents(old_relfilenumber)
+        );
+        assert_eq!((0, None), committed_index_contents(new_relfilenumber));
+
+        // Once a REINDEX commits, only the new entries are left
+        Spi::run("REINDEX INDEX id_idx").unwrap();
+        let new_relfilenumber = index_relfilenumber("id_idx");
+        unsafe {
+            crate::transaction::transaction_callback(
+                pg_sys::XactEvent::XACT_EVENT_PRE_COMMIT,
+                std::ptr::null_mut(),
+            );
+        }
+        commit_session_transaction();
+        assert_eq!((0, None), committed_index_contents(old_relfilenumber));
+        assert_eq!(
+            (100, Some(IndexState::Readable)),
+            committed_index_contents(new_relfilenumber)
+        );
+
+        clear_committed_table("test");
+    }
+
+    #[pg_test]
+    fn aborted_online_build_is_cleared() {
+        use crate::iam::state::IndexState;
+
+        Spi::run("CREATE TABLE test (id INTEGER) USING pgfdb_table").unwrap();
+        Spi::run("INSERT INTO test(id) SELECT i FROM generate_series(1, 100) AS i").unwrap();
+        commit_session_transaction();
+
+        // The rows are committed, so the index is built online in separate transactions
+        Spi::run("CREATE INDEX id_idx ON test USING pgfdb(id)").unwrap();
+        let index_oid: pg_sys::Oid = Spi::get_one("SELECT 'id_idx'::regclass::oid")
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            (100, Some(IndexState::Readable)),
+            committed_index_contents(index_oid)
+        );
+
+        // Rolling back the transaction drops the index again, so the committed entries and state must be cleared
+        abort_session_transaction();
+        assert_eq!((0, None), committed_index_contents(index_oid));
+
+        clear_committed_table("test");
+    }
+
+    #[pg_test]
+    fn operator_classes_are_valid() {
+        let result: Option<bool> = Spi::get_one(
+            "SELECT bool_and(amvalidate(opc.oid))
+            FROM pg_opclass opc JOIN pg_am am ON am.oid = opc.opcmethod
+            WHERE am.amname = 'pgfdb'",
+        )
+        .unwrap();
+        assert_eq!(Some(true), result);
+    }
+
+    #[pg_test(error = "pgfdb indexes don't support data type inet[]")]
+    fn create_index_on_unsupported_type() {
+        Spi::run("CREATE TABLE test (addresses INET[]) USING pgfdb_table").unwrap();
+        Spi::run("CREATE INDEX addresses_idx ON test USING pgfdb(addresses)").unwrap();
+    }
+
+    #[pg_test]
+    fn select_with_parallel_index_scan() {
+        Spi::run("CREATE TABLE test (id INTEGER, name TEXT) USING pgfdb_table").unwrap();
+        Spi::run("CREATE INDEX id_idx ON test USING pgfdb(id)").unwrap();
+        Spi::run(
+            "INSERT INTO test(id, name) SELECT i, 'name' || i FROM generate_series(1, 2000) AS i",
+        )
+        .unwrap();
+
+        Spi::run("SET enable_seqscan=0").unwrap();
+        Spi::run("SET parallel_setup_cost=0").unwrap();
+        Spi::run("SET parallel_tuple_cost=0").unwrap();
+        Spi::run("SET min_parallel_index_scan_size=0").unwrap();
+        Spi::run("SET max_parallel_workers_per_gather=2").unwrap();
+
+        // The rows have been written in this transaction, so the leader scans them all on its own
+        let query = "SELECT count(*), sum(id) FROM test WHERE id > 500";
+        let explain = Spi::explain(query).unwrap();
+        assert!(
+            explain.0.to_string().contains("\"Parallel Aware\":true"),
+            "expected query plan to use a parallel index scan: {:?}",
+            explain.0.to_string()
+        );
+        let (count, sum): (Option<i64>, Option<i64>) = Spi::get_two(query).unwrap();
+        assert_eq!(Some(1500), count);
+        assert_eq!(Some((501..=2000).sum()), sum);
+    }
+
+    #[pg_test]
+    fn create_index_with_parallel_workers() {
+        use crate::iam::state::IndexState;
+
+        Spi::run(
+            "CREATE TABLE test (id INTEGER, name TEXT) USING pgfdb_table WITH (parallel_workers = 2)",
+        )
+        .unwrap();
+        // Enough data for FDB to split the table into several chunks
+        Spi::run(
+            "INSERT INTO test(id, name) SELECT i, repeat('x', 200) FROM generate_series(1, 10000) AS i",
+        )
+        .unwrap();
+        // Workers can only see committed rows, and the build is only split between them if the session
+        // transaction hasn't written to the table
+        commit_session_transaction();
+
+        Spi::run("SET max_parallel_maintenance_workers=2").unwrap();
+        Spi::run("CREATE INDEX id_idx ON test USING pgfdb(id)").unwrap();
+
+        // Postgres plans as many workers as the table allows, which it sets as `ii_ParallelWorkers` for the build
+        let table_oid: pg_sys::Oid = Spi::get_one("SELECT 'test'::regclass::oid")
+            .unwrap()
+            .unwrap();
+        let index_oid: pg_sys::Oid = Spi::get_one("SELECT 'id_idx'::regclass::oid")
+            .unwrap()
+            .unwrap();
+        let workers = unsafe { pg_sys::plan_create_index_workers(table_oid, index_oid) };
+        assert_eq!(2, workers);
+
+        // Each row must have been indexed exactly once by one of the participants
+        assert_eq!(
+            (10000, Some(IndexState::Readable)),
+            committed_index_contents(index_oid)
+        );
+        let result: Option<i64> =
+            Spi::get_one("SELECT count(*) FROM pgfdb_verify_index('id_idx', true)").unwrap();
+        assert_eq!(Some(0), result);
+
+        Spi::run("SET enable_seqscan=0").unwrap();
+        let result: Option<i64> = Spi::get_one("SELECT count(*) FROM test WHERE id <= 10").unwrap();
+        assert_eq!(Some(10), result);
+
+        clear_committed_table("test");
+    }
+
+    #[pg_test]
+    fn vacuum_clears_dangling_index_entries() {
+        Spi::run("CREATE TABLE test (id INTEGER) USING pgfdb_table").unwrap();
+        Spi::run("CREATE INDEX id_idx ON test USING pgfdb(id)").unwrap();
+        Spi::run("INSERT INTO test(id) VALUES (1), (2), (3)").unwrap();
+
+        let (table_oid, index_oid): (Option<pg_sys::Oid>, Option<pg_sys::Oid>) =
+            Spi::get_two("SELECT 'test'::regclass::oid, 'id_idx'::regclass::oid").unwrap();
+        let row_id: i64 =
+            Spi::get_one("SELECT (ctid::text::point)[0]::bigint FROM test WHERE id = 1")
+                .unwrap()
+                .unwrap();
+
+        // Leave behind an entry for a row which doesn't exist. VACUUM can't run inside the test transaction, so
+        // we vacuum the indexes directly.
+        let txn = crate::transaction::get_transaction();
+        let index_subspace = crate::subspace::index(index_oid.unwrap());
+        txn.set(&index_subspace.pack(&(4, row_id + 1)), &[]);
+
+        let count_dangling = || -> Option<i64> {
+            Spi::get_one("SELECT count(*) FROM pgfdb_verify_index('id_idx') WHERE kind = 'extra'")
+                .unwrap()
+        };
+        assert_eq!(Some(1), count_dangling());
+
+        unsafe {
+            let table_rel = pg_sys::RelationIdGetRelation(table_oid.unwrap());
+            crate::iam::vacuum::vacuum_indexes(table_rel, PgLogLevel::INFO, std::ptr::null_mut());
+            pg_sys::RelationClose(table_rel);
+        }
+
+        assert_eq!(Some(0), count_dangling());
+        let result: Option<i64> = Spi::get_one("SELECT count(*) FROM test WHERE id >= 1").unwrap();
+        assert_eq!(Some(3), result);
+    }
+
+    #[pg_test]
+    fn verify_index_reports_inconsistent_entries() {
+        Spi::run("CREATE TABLE test (id INTEGER) USING pgfdb_table").unwrap();
+        Spi::run("CREATE INDEX id_idx ON test USING pgfdb(id)").unwrap();
+        Spi::run("INSERT INTO test(id) VALUES (1), (2), (3)").unwrap();
+
+        let verify = |heapallindexed: bool| -> Option<String> {
+            Spi::get_one(&format!(
+                "SELECT string_agg(kind, ',' ORDER BY kind) FROM pgfdb_verify_index('id_idx', {heapallindexed})"
+            ))
+            .unwrap()
+        };
+        assert_eq!(None, verify(true));
+
+        let index_oid: pg_sys::Oid = Spi::get_one("SELECT 'id_idx'::regclass::oid")
+            .unwrap()
+            .unwrap();
+        let row_id = |id: i32| -> i64 {
+            Spi::get_one(&format!(
+                "SELECT (ctid::text::point)[0]::bigint FROM test WHERE id = {id}"
+            ))
+            .unwrap()
+            .unwrap()
+        };
+        let (first_row_id, second_row_id) = (row_id(1), row_id(2));
+
+        // Add an entry with outdated values, add an entry for a row which doesn't exist and remove the entry of a row
+        let txn = crate::transaction::get_transaction();
+        let index_subspace = crate::subspace::index(index_oid);
+        txn.set(&index_subspace.pack(&(42, first_row_id)), &[]);
+        txn.set(&index_subspace.pack(&(4, first_row_id + 1)), &[]);
+        txn.clear(&index_subspace.pack(&(2, second_row_id)));
+
+        assert_eq!(Some("extra,mismatched".to_string()), verify(false));
+        assert_eq!(Some("extra,mismatched,missing".to_string()), verify(true));
+    }
+
+    #[pg_test]
+    fn partial_index_maintenance() {
+        Spi::run(
+            "CREATE TABLE test (id INTEGER, email TEXT, deleted_at TIMESTAMP) USING pgfdb_table",
+        )
+        .unwrap();
+        Spi::run(
+            "INSERT INTO test(id, email, deleted_at) VALUES
+                (1, 'a@example.com', NULL), (2, 'b@example.com', '2025-01-01'), (3, 'a@example.com', NULL)",
+        )
+        .unwrap();
+        Spi::run(
+            "CREATE INDEX active_email_idx ON test USING pgfdb(email) WHERE deleted_at IS NULL",
+        )
+        .unwrap();
+
+        // Rows move in and out of the index as they start and stop matching its predicate
+        Spi::run(
+            "INSERT INTO test(id, email, deleted_at) VALUES
+                (4, 'a@example.com', '2025-01-01'), (5, 'b@example.com', NULL)",
+        )
+        .unwrap();
+        Spi::run("UPDATE test SET deleted_at = '2025-01-02' WHERE id = 1").unwrap();
+        Spi::run("UPDATE test SET deleted_at = NULL WHERE id = 2").unwrap();
+        Spi::run("DELETE FROM test WHERE id = 5").unwrap();
+        Spi::run("SET enable_seqscan=0").unwrap();
+
+        // The predicate is implied by the index and not rechecked, so stale keys would show up here
+        let cases = vec![
+            ("email = 'a@example.com'", "3"),
+            ("email = 'b@example.com'", "2"),
+            ("email >= 'a@example.com'", "2,3"),
+        ];
+
+        for (condition, expected) in cases {
+            let query = format!(
+                "SELECT string_agg(id::text, ',' ORDER BY id) FROM test WHERE {condition} AND deleted_at IS NULL"
+            );
+            let explain = Spi::explain(&query).unwrap();
+            assert!(
+                format!("{:?}", explain).contains("Index Name"),
+                "expected query plan to use index: {:?}",
+                explain.0.to_string()
+            );
+
+            let result: Option<String> = Spi::get_one(&query).unwrap();
+            assert_eq!(
+                Some(expected.to_string()),
+                result,
+                "unexpected result for {condition}"
+            );
+        }
+    }
+
+    #[pg_test]
+    fn select_with_expression_index() {
+        Spi::run("CREATE TABLE test (id INTEGER, email TEXT) USING pgfdb_table").unwrap();
+        Spi::run(
+            "INSERT INTO test(id, email) VALUES
+                (1, 'Alice@Example.com'), (2, 'bob@example.com'), (3, 'ALICE@example.com')",
+        )
+        .unwrap();
+        Spi::run("CREATE INDEX lower_email_idx ON test USING pgfdb(lower(email))").unwrap();
+        Spi::run("CREATE INDEX email_length_idx ON test USING pgfdb(length(email))").unwrap();
+
+        Spi::run(
+            "INSERT INTO test(id, email) VALUES (4, 'alice@EXAMPLE.com'), (5, 'Carol@Example.org')",
+        )
+        .unwrap();
+        Spi::run("UPDATE test SET email = 'alice@example.net' WHERE id = 3").unwrap();
+        Spi::run("DELETE FROM test WHERE id = 1").unwrap();
+        Spi::run("SET enable_seqscan=0").unwrap();
+
+        // The keys are encoded based on the type of the expression rather than the column it's computed from
+        let cases = vec![
+            ("lower(email) = 'alice@example.com'", "4"),
+            ("lower(email) = 'alice@example.net'", "3"),
+            ("lower(email) > 'b'", "2,5"),
+            ("length(email) = 15", "2"),
+            ("length(email) > 15", "3,4,5"),
+        ];
+
+        for (condition, expected) in cases {
+            let query =
+                format!("SELECT string_agg(id::text, ',' ORDER BY id) FROM test WHERE {condition}");
+            let explain = Spi::explain(&query).unwrap();
+            assert!(
+                format!("{:?}", explain).contains("Index Name"),
+                "expected query plan to use index: {:?}",
+                explain.0.to_string()
+            );
+
+            let result: Option<String> = Spi::get_one(&query).unwrap();
+            assert_eq!(
+                Some(expected.to_string()),
+                result,
+                "unexpected result for {condition}"
+            );
+        }
+    }
+
     const INTEGER_TEST_VALUES: (&'static str, &'static str, &'static str) = ("1", "2", "3");
     const FLOAT_TEST_VALUES: (&'static str, &'static str, &'static str) = ("1.1", "2.2", "3.3");
     const STRING_TEST_VALUES: (&'static str, &'static str, &'static str) =
@@ -220,256 +752,1017 @@ mod tests {
         "'c552b673-47ba-4734-bd34-36905f1bf815'",
     );
 
+    const TIMESTAMP_TEST_VALUES: (&'static str, &'static str, &'static str) = (
+        "'2025-01-01 12:00:00'",
+        "'2025-01-02 12:00:00'",
+        "'2025-02-01 12:00:00'",
+    );
+    const TIMESTAMPTZ_TEST_VALUES: (&'static str, &'static str, &'static str) = (
+        "'2025-01-01 12:00:00+00'",
+        "'2025-01-01 12:00:00-02'",
+        "'2025-01-02 12:00:00+00'",
+    );
+    const DATE_TEST_VALUES: (&'static str, &'static str, &'static str) =
+        ("'-infinity'", "'2025-01-01'", "'2025-01-02'");
+    const TIME_TEST_VALUES: (&'static str, &'static str, &'static str) =
+        ("'01:00:00'", "'02:00:00'", "'23:59:59.5'");
+    const INTERVAL_TEST_VALUES: (&'static str, &'static str, &'static str) =
+        ("'-1 day'", "'36 hours'", "'1 month'");
+    const BYTEA_TEST_VALUES: (&'static str, &'static str, &'static str) =
+        ("'\\x00'", "'\\x0001'", "'\\x01'");
+    const CHAR_TEST_VALUES: (&'static str, &'static str, &'static str) = ("'a'", "'b'", "'c'");
+    const NUMERIC_TEST_VALUES: (&'static str, &'static str, &'static str) =
+        ("'-12.5'", "'0.001'", "'1000'");
+
+    #[pg_test]
+    fn select_eq_with_index() {
+        let cases = vec![
+            ("INTEGER", INTEGER_TEST_VALUES),
+            ("BIGINT", INTEGER_TEST_VALUES),
+            ("SMALLINT", INTEGER_TEST_VALUES),
+            ("REAL", FLOAT_TEST_VALUES),
+            ("FLOAT", FLOAT_TEST_VALUES),
+            ("TEXT", STRING_TEST_VALUES),
+            ("UUID", UUID_TEST_VALUES),
+            ("TIMESTAMP", TIMESTAMP_TEST_VALUES),
+            ("TIMESTAMPTZ", TIMESTAMPTZ_TEST_VALUES),
+            ("DATE", DATE_TEST_VALUES),
+            ("TIME", TIME_TEST_VALUES),
+            ("INTERVAL", INTERVAL_TEST_VALUES),
+            ("BYTEA", BYTEA_TEST_VALUES),
+            ("\"char\"", CHAR_TEST_VALUES),
+            ("NUMERIC", NUMERIC_TEST_VALUES),
+            ("CHAR(5)", STRING_TEST_VALUES),
+        ];
+
+        for (column_type, values) in cases {
+            assert_index_scan_count(column_type, values, "=", 2);
+        }
+    }
+
+    #[pg_test]
+    fn select_not_eq_with_index() {
+        let cases = vec![
+            ("INTEGER", INTEGER_TEST_VALUES),
+            ("BIGINT", INTEGER_TEST_VALUES),
+            ("SMALLINT", INTEGER_TEST_VALUES),
+            ("REAL", FLOAT_TEST_VALUES),
+            ("FLOAT", FLOAT_TEST_VALUES),
+            ("TEXT", STRING_TEST_VALUES),
+            ("UUID", UUID_TEST_VALUES),
+            ("TIMESTAMP", TIMESTAMP_TEST_VALUES),
+            ("TIMESTAMPTZ", TIMESTAMPTZ_TEST_VALUES),
+            ("DATE", DATE_TEST_VALUES),
+            ("TIME", TIME_TEST_VALUES),
+            ("INTERVAL", INTERVAL_TEST_VALUES),
+            ("BYTEA", BYTEA_TEST_VALUES),
+            ("\"char\"", CHAR_TEST_VALUES),
+            ("NUMERIC", NUMERIC_TEST_VALUES),
+            ("CHAR(5)", STRING_TEST_VALUES),
+        ];
+
+        for (column_type, values) in cases {
+            assert_index_scan_count(column_type, values, "!=", 4);
+        }
+    }
+
+    #[pg_test]
+    fn select_lt_with_index() {
+        let cases = vec![
+            ("INTEGER", INTEGER_TEST_VALUES),
+            ("BIGINT", INTEGER_TEST_VALUES),
+            ("SMALLINT", INTEGER_TEST_VALUES),
+            ("REAL", FLOAT_TEST_VALUES),
+            ("FLOAT", FLOAT_TEST_VALUES),
+            ("TIMESTAMP", TIMESTAMP_TEST_VALUES),
+            ("TIMESTAMPTZ", TIMESTAMPTZ_TEST_VALUES),
+            ("DATE", DATE_TEST_VALUES),
+            ("TIME", TIME_TEST_VALUES),
+            ("INTERVAL", INTERVAL_TEST_VALUES),
+            ("BYTEA", BYTEA_TEST_VALUES),
+            ("\"char\"", CHAR_TEST_VALUES),
+            ("NUMERIC", NUMERIC_TEST_VALUES),
+            ("TEXT", STRING_TEST_VALUES),
+            ("CHAR(5)", STRING_TEST_VALUES),
+        ];
+
+        for (column_type, values) in cases {
+            assert_index_scan_count(column_type, values, "<", 2);
+        }
+    }
+
+    #[pg_test]
+    fn select_lte_with_index() {
+        let cases = vec![
+            ("INTEGER", INTEGER_TEST_VALUES),
+            ("BIGINT", INTEGER_TEST_VALUES),
+            ("SMALLINT", INTEGER_TEST_VALUES),
+            ("REAL", FLOAT_TEST_VALUES),
+            ("FLOAT", FLOAT_TEST_VALUES),
+            ("TIMESTAMP", TIMESTAMP_TEST_VALUES),
+            ("TIMESTAMPTZ", TIMESTAMPTZ_TEST_VALUES),
+            ("DATE", DATE_TEST_VALUES),
+            ("TIME", TIME_TEST_VALUES),
+            ("INTERVAL", INTERVAL_TEST_VALUES),
+            ("BYTEA", BYTEA_TEST_VALUES),
+            ("\"char\"", CHAR_TEST_VALUES),
+            ("NUMERIC", NUMERIC_TEST_VALUES),
+            ("TEXT", STRING_TEST_VALUES),
+            ("CHAR(5)", STRING_TEST_VALUES),
+        ];
+
+        for (column_type, values) in cases {
+            assert_index_scan_count(column_type, values, "<=", 4);
+        }
+    }
+
+    #[pg_test]
+    fn select_gt_with_index() {
+        let cases = vec![
+            ("INTEGER", INTEGER_TEST_VALUES),
+            ("BIGINT", INTEGER_TEST_VALUES),
+            ("SMALLINT", INTEGER_TEST_VALUES),
+            ("REAL", FLOAT_TEST_VALUES),
+            ("FLOAT", FLOAT_TEST_VALUES),
+            ("TIMESTAMP", TIMESTAMP_TEST_VALUES),
+            ("TIMESTAMPTZ", TIMESTAMPTZ_TEST_VALUES),
+            ("DATE", DATE_TEST_VALUES),
+            ("TIME", TIME_TEST_VALUES),
+            ("INTERVAL", INTERVAL_TEST_VALUES),
+            ("BYTEA", BYTEA_TEST_VALUES),
+            ("\"char\"", CHAR_TEST_VALUES),
+            ("NUMERIC", NUMERIC_TEST_VALUES),
+            ("TEXT", STRING_TEST_VALUES),
+            ("CHAR(5)", STRING_TEST_VALUES),
+        ];
+
+        for (column_type, values) in cases {
+            assert_index_scan_count(column_type, values, ">", 2);
+        }
+    }
+
+    #[pg_test]
+    fn select_gte_with_index() {
+        let cases = vec![
+            ("INTEGER", INTEGER_TEST_VALUES),
+            ("BIGINT", INTEGER_TEST_VALUES),
+            ("SMALLINT", INTEGER_TEST_VALUES),
+            ("REAL", FLOAT_TEST_VALUES),
+            ("FLOAT", FLOAT_TEST_VALUES),
+            ("TIMESTAMP", TIMESTAMP_TEST_VALUES),
+            ("TIMESTAMPTZ", TIMESTAMPTZ_TEST_VALUES),
+            ("DATE", DATE_TEST_VALUES),
+            ("TIME", TIME_TEST_VALUES),
+            ("INTERVAL", INTERVAL_TEST_VALUES),
+            ("BYTEA", BYTEA_TEST_VALUES),
+            ("\"char\"", CHAR_TEST_VALUES),
+            ("NUMERIC", NUMERIC_TEST_VALUES),
+            ("TEXT", STRING_TEST_VALUES),
+            ("CHAR(5)", STRING_TEST_VALUES),
+        ];
+
+        for (column_type, values) in cases {
+            assert_index_scan_count(column_type, values, ">=", 4);
+        }
+    }
+
+    #[pg_test]
+    fn select_with_boolean_index() {
+        Spi::run("CREATE TABLE test (flag BOOLEAN) USING pgfdb_table").unwrap();
+        Spi::run("CREATE INDEX flag_idx ON test USING pgfdb(flag)").unwrap();
+        Spi::run("INSERT INTO test(flag) VALUES (false), (true), (true), (NULL)").unwrap();
+        Spi::run("SET enable_seqscan=0").unwrap();
+
+        // Booleans only have two values so they don't fit the cases used for other types. We compare against
+        // subqueries as Postgres would otherwise simplify conditions like `flag = true` into just `flag`.
+        let cases = vec![
+            (2, "flag = (SELECT true)"),
+            (1, "flag != (SELECT true)"),
+            (1, "flag < (SELECT true)"),
+            (3, "flag <= (SELECT true)"),
+            (2, "flag > (SELECT false)"),
+            (3, "flag >= (SELECT false)"),
+        ];
+
+        for (expected, condition) in cases {
+            let query = format!("SELECT count(*) FROM test WHERE {condition}");
+            let explain = Spi::explain(&query).unwrap();
+            assert!(
+                format!("{:?}", explain).contains("Index Name"),
+                "expected query plan to use index: {:?}",
+                explain.0.to_string()
+            );
+
+            let result: Option<i64> = Spi::get_one(&query).unwrap();
+            assert_eq!(Some(expected), result, "unexpected result for {condition}");
+        }
+    }
+
+    #[pg_test]
+    fn select_with_numeric_index() {
+        Spi::run("CREATE TABLE test (amount NUMERIC) USING pgfdb_table").unwrap();
+        Spi::run("CREATE INDEX amount_idx ON test USING pgfdb(amount)").unwrap();
+        Spi::run(
+            "INSERT INTO test(amount) VALUES
+                ('NaN'), ('Infinity'), ('-Infinity'), (2.5), (2.50), (2.500),
+                (-2.5), (-2.25), (0), (-0.0), (123456789012345678901234567890.5), (0.0001)",
+        )
+        .unwrap();
+        Spi::run("SET enable_seqscan=0").unwrap();
+
+        let cases = vec![
+            // Equal values with different scales must match each other
+            (3, "amount = 2.5"),
+            (2, "amount = 0"),
+            (1, "amount = 'NaN'"),
+            // Ordering of special values, negatives and values with different exponents
+            (3, "amount < -0.0001"),
+            (3, "amount > 100"),
+            (1, "amount > 'Infinity'"),
+            (11, "amount >= -2.5"),
+            // Integers compared to numerics are cast to numerics
+            (2, "amount = CAST(0 AS INTEGER)"),
+            (6, "amount < CAST(1 AS BIGINT)"),
+        ];
+
+        for (expected, condition) in cases {
+            let query = format!("SELECT count(*) FROM test WHERE {condition}");
+            let explain = Spi::explain(&query).unwrap();
+            assert!(
+                format!("{:?}", explain).contains("Index Name"),
+                "expected query plan to use index: {:?}",
+                explain.0.to_string()
+            );
+
+            let result: Option<i64> = Spi::get_one(&query).unwrap();
+            assert_eq!(Some(expected), result, "unexpected result for {condition}");
+        }
+    }
+
+    #[pg_test]
+    fn numeric_operators_of_other_indexes_are_unaffected() {
+        // Comparing a numeric to an integer must still resolve to the built-in operators, which btree indexes
+        // on regular tables support
+        Spi::run("CREATE TABLE heap_test (amount NUMERIC)").unwrap();
+        Spi::run("CREATE INDEX heap_amount_idx ON heap_test USING btree(amount)").unwrap();
+        Spi::run("INSERT INTO heap_test SELECT i FROM generate_series(1, 100) AS i").unwrap();
+        Spi::run("SET enable_seqscan=0").unwrap();
+
+        let explain =
+            Spi::explain("SELECT count(*) FROM heap_test WHERE amount = CAST(5 AS INTEGER)")
+                .unwrap();
+        assert!(
+            format!("{:?}", explain).contains("heap_amount_idx"),
+            "expected query plan to use btree index: {:?}",
+            explain.0.to_string()
+        );
+
+        let namespace: Option<String> = Spi::get_one(
+            "SELECT oprnamespace::regnamespace::text FROM pg_operator
+            WHERE oid = 'pg_catalog.=(numeric, numeric)'::regoperator",
+        )
+        .unwrap();
+        assert_eq!(Some("pg_catalog".to_string()), namespace);
+    }
+
+    #[pg_test]
+    fn select_with_collated_text_index() {
+        // The "C" collation compares bytes, so uppercase letters sort before lowercase ones. ICU collations
+        // aren't available in every build of Postgres, so only test them if the root collation exists.
+        let mut cases = vec![("\"C\"", (2, 3, 2), "B,a,c,d,é")];
+        let has_icu: Option<bool> =
+            Spi::get_one("SELECT EXISTS (SELECT 1 FROM pg_collation WHERE collname = 'und-x-icu')")
+                .unwrap();
+        if has_icu == Some(true) {
+            cases.push(("\"und-x-icu\"", (1, 3, 2), "a,B,c,d,é"));
+        }
+
+        for (collation, (expected_lt, expected_lte, expected_gt), expected_order) in cases {
+            Spi::run(&format!(
+                "CREATE TABLE test (name TEXT COLLATE {collation}) USING pgfdb_table"
+            ))
+            .unwrap();
+            Spi::run("CREATE INDEX name_idx ON test USING pgfdb(name)").unwrap();
+            Spi::run("INSERT INTO test(name) VALUES ('d'), ('é'), ('B'), ('a'), ('c')").unwrap();
+            Spi::run("SET enable_seqscan=0").unwrap();
+
+            let conditions = vec![
+                (expected_lt, "name < 'b'"),
+                (expected_lte, "name <= 'c'"),
+                (expected_gt, "name > 'c'"),
+                (1, "name = 'B'"),
+            ];
+            for (expected, condition) in conditions {
+                let query = format!("SELECT count(*) FROM test WHERE {condition}");
+                let explain = Spi::explain(&query).unwrap();
+                assert!(
+                    format!("{:?}", explain).contains("Index Name"),
+                    "expected query plan to use index: {:?}",
+                    explain.0.to_string()
+                );
+
+                let result: Option<i64> = Spi::get_one(&query).unwrap();
+                assert_eq!(
+                    Some(expected),
+                    result,
+                    "unexpected result for {condition} with collation {collation}"
+                );
+            }
+
+            // The index order must match the order of the collation
+            let query =
+                "SELECT string_agg(name, ',') FROM (SELECT name FROM test ORDER BY name) AS sorted";
+            let explain = Spi::explain(query).unwrap();
+            assert!(
+                !format!("{:?}", explain).contains("Sort Key"),
+                "expected query plan to use index order: {:?}",
+                explain.0.to_string()
+            );
+            let result: Option<String> = Spi::get_one(query).unwrap();
+            assert_eq!(Some(expected_order.to_string()), result);
+
+            Spi::run("DROP TABLE test").unwrap();
+        }
+    }
+
+    #[pg_test]
+    fn select_prefix_with_index() {
+        // Prefix searches are turned into range conditions by the planner, which only works for indexes with the
+        // "C" collation as other collations don't keep values with the same prefix together
+        Spi::run("CREATE TABLE test (path TEXT, name VARCHAR(20)) USING pgfdb_table").unwrap();
+        Spi::run("CREATE INDEX path_idx ON test USING pgfdb(path COLLATE \"C\")").unwrap();
+        Spi::run("CREATE INDEX name_idx ON test USING pgfdb(name COLLATE \"C\")").unwrap();
+        Spi::run(
+            "INSERT INTO test(path, name) VALUES
+                ('/usr/bin', 'abc'), ('/usr/lib', 'abcd'), ('/usr', 'abd'), ('/var/log', 'ab'),
+                ('/usr/binary', 'Abc'), ('/', NULL)",
+        )
+        .unwrap();
+        Spi::run("SET enable_seqscan=0").unwrap();
+
+        let cases = vec![
+            (3, "path LIKE '/usr/%'"),
+            (2, "path LIKE '/usr/bin%'"),
+            (1, "path LIKE '/usr/bin%' AND path LIKE '%ary'"),
+            (4, "path ^@ '/usr'"),
+            (1, "path ^@ '/var/'"),
+            (0, "path ^@ '/tmp'"),
+            (3, "starts_with(path, '/usr/')"),
+            (2, "name LIKE 'abc%'"),
+            (0, "name LIKE 'abe%'"),
+            (4, "starts_with(name, 'ab')"),
+        ];
+
+        for (expected, condition) in cases {
+            let query = format!("SELECT count(*) FROM test WHERE {condition}");
+            let explain = Spi::explain(&query).unwrap();
+            assert!(
+                format!("{:?}", explain).contains("Index Name"),
+                "expected query plan to use index: {:?}",
+                explain.0.to_string()
+            );
+
+            let result: Option<i64> = Spi::get_one(&query).unwrap();
+            assert_eq!(Some(expected), result, "unexpected result for {condition}");
+        }
+    }
+
+    #[pg_test]
+    fn select_with_enum_domain_array_and_composite_index() {
+        Spi::run("CREATE TYPE mood AS ENUM ('sad', 'ok', 'happy')").unwrap();
+        Spi::run("CREATE DOMAIN short_text AS TEXT CHECK (length(VALUE) < 10)").unwrap();
+        Spi::run("CREATE TYPE labeled AS (x INTEGER, label TEXT)").unwrap();
+
+        let cases = vec![
+            (
+                "mood",
+                "('happy'), ('sad'), ('ok'), (NULL)",
+                vec![
+                    (1, "value < 'ok'"),
+                    (2, "value >= 'ok'"),
+                    (1, "value = 'happy'"),
+                ],
+                "sad;ok;happy",
+            ),
+            (
+                "short_text",
+                "('b'), ('c'), ('a')",
+                vec![(1, "value < 'b'"), (2, "value >= 'b'"), (1, "value = 'c'")],
+                "a;b;c",
+            ),
+            (
+                "INTEGER[]",
+                // The last two arrays have the same elements and are ordered by their dimensions before their
+                // lower bounds
+                "('{1,2}'), ('{1,2,3}'), ('{1,NULL}'), ('{0,5}'), ('{2}'), ('{{1,2}}'),
+                ('{{{1,2},{3,4},{5,6}}}'), ('[2:2][1:2][1:3]={{{1,2,3},{4,5,6}}}')",
+                vec![
+                    (3, "value < '{1,2,3}'"),
+                    (6, "value > '{1,2}'"),
+                    (1, "value = '{1,2}'"),
+                    (1, "value = '{1,NULL}'"),
+                ],
+                "{0,5};{1,2};{{1,2}};{1,2,3};[2:2][1:2][1:3]={{{1,2,3},{4,5,6}}};{{{1,2},{3,4},{5,6}}};{1,NULL};{2}",
+            ),
+            (
+                "labeled",
+                "('(1,b)'), ('(1,a)'), ('(0,z)'), ('(1,)')",
+                vec![
+                    (2, "value < '(1,b)'"),
+                    (1, "value > '(1,b)'"),
+                    (1, "value = '(1,a)'"),
+                ],
+                "(0,z);(1,a);(1,b);(1,)",
+            ),
+        ];
+
+        for (i, (column_type, values, conditions, expected_order)) in cases.into_iter().enumerate()
+        {
+            let table = format!("test_{i}");
+            Spi::run(&format!(
+                "CREATE TABLE {table} (value {column_type}) USING pgfdb_table"
+            ))
+            .unwrap();
+            Spi::run(&format!(
+                "CREATE INDEX {table}_value_idx ON {table} USING pgfdb(value)"
+            ))
+            .unwrap();
+            Spi::run(&format!("INSERT INTO {table}(value) VALUES {values}")).unwrap();
+            Spi::run("SET enable_seqscan=0").unwrap();
+
+            for (expected, condition) in conditions {
+                let query = format!("SELECT count(*) FROM {table} WHERE {condition}");
+                let explain = Spi::explain(&query).unwrap();
+                assert!(
+                    format!("{:?}", explain).contains("Index Name"),
+                    "expected query plan to use index: {:?}",
+                    explain.0.to_string()
+                );
+
+                let result: Option<i64> = Spi::get_one(&query).unwrap();
+                assert_eq!(
+                    Some(expected),
+                    result,
+                    "unexpected result for {condition} on {column_type}"
+                );
+            }
+
+            // The index order must match how Postgres compares the values
+            let query = format!(
+                "SELECT string_agg(value::text, ';') FROM (SELECT value FROM {table} WHERE value IS NOT NULL ORDER BY value) AS sorted"
+            );
+            let explain = Spi::explain(&query).unwrap();
+            assert!(
+                !format!("{:?}", explain).contains("Sort Key"),
+                "expected query plan to use index order: {:?}",
+                explain.0.to_string()
+            );
+            let result: Option<String> = Spi::get_one(&query).unwrap();
+            assert_eq!(Some(expected_order.to_string()), result);
+        }
+    }
+
+    #[pg_test(
+        error = "adding this value renumbers the values of enum mood, which pgfdb index mood_idx depends on"
+    )]
+    fn enum_renumbering_with_index() {
+        Spi::run("CREATE TYPE mood AS ENUM ('sad', 'happy')").unwrap();
+        Spi::run("CREATE TABLE test (mood mood) USING pgfdb_table").unwrap();
+        Spi::run("CREATE INDEX mood_idx ON test USING pgfdb(mood)").unwrap();
+
+        // Each value gets a sort order halfway between the last one and 'happy', until there's no room left and
+        // Postgres has to renumber the existing values
+        for i in 0..100 {
+            Spi::run(&format!(
+                "ALTER TYPE mood ADD VALUE 'mood_{i}' BEFORE 'happy'"
+            ))
+            .unwrap();
+        }
+    }
+
+    #[pg_test]
+    fn select_lt_on_nulls_with_index() {
+        Spi::run("CREATE TABLE test (id INTEGER) USING pgfdb_table").unwrap();
+        Spi::run("CREATE INDEX id_idx ON test USING pgfdb(id)").unwrap();
+
+        // Ensure the select will use our index
+        Spi::run("SET enable_seqscan=0").unwrap();
+        let explain = Spi::explain("SELECT count(*) FROM test WHERE id < 2;").unwrap();
+        assert!(
+            format!("{:?}", explain).contains("Index Name"),
+            "expected query plan to use index: {:?}",
+            explain.0.to_string()
+        );
+
+        // Ensure querying using the index does not count the NULL value
+        Spi::run("INSERT INTO test(id) VALUES (NULL), (1), (2), (3), (3)").unwrap();
+        let result: Option<i64> = Spi::get_one("SELECT count(*) FROM test WHERE id < 2").unwrap();
+        assert_eq!(Some(1), result);
+    }
+
+    #[pg_test]
+    fn select_null_with_index() {
+        Spi::run("CREATE TABLE test (id INTEGER) USING pgfdb_table").unwrap();
+        Spi::run("CREATE INDEX id_idx ON test USING pgfdb(id)").unwrap();
+
+        // Ensure the select will use our index
+        Spi::run("SET enable_seqscan=0").unwrap();
+        let explain = Spi::explain("SELECT count(*) FROM test WHERE id IS NULL;").unwrap();
+        assert!(
+            format!("{:?}", explain).contains("Index Name"),
+            "expected query plan to use index: {:?}",
+            explain.0.to_string()
+        );
+
+        // Ensure querying using the index returns the correct results
+        Spi::run("INSERT INTO test(id) VALUES (1), (NULL), (NULL), (3)").unwrap();
+        let result: Option<i64> =
+            Spi::get_one("SELECT count(*) FROM test WHERE id IS NULL").unwrap();
+        assert_eq!(Some(2), result);
+    }
+
+    #[pg_test]
+    fn select_not_null_with_index() {
+        Spi::run("CREATE TABLE test (id INTEGER) USING pgfdb_table").unwrap();
+        Spi::run("CREATE INDEX id_idx ON test USING pgfdb(id)").unwrap();
+
+        // Ensure the select will use our index
+        Spi::run("SET enable_seqscan=0").unwrap();
+        let explain = Spi::explain("SELECT count(*) FROM test WHERE id IS NOT NULL;").unwrap();
+        assert!(
+            format!("{:?}", explain).contains("Index Name"),
+            "expected query plan to use index: {:?}",
+            explain.0.to_string()
+        );
+
+        // Ensure querying using the index returns the correct results
+        Spi::run("INSERT INTO test(id) VALUES (1), (NULL), (NULL), (3)").unwrap();
+        let result: Option<i64> =
+            Spi::get_one("SELECT count(*) FROM test WHERE id IS NOT NULL").unwrap();
+        assert_eq!(Some(2), result);
+    }
+
+    #[pg_test]
+    fn select_eq_with_index_and_non_indexed_column() {
+        // Create a table with a non-indexed column first, followed by an indexed column
+        // This ensures we don't rely on column numbering between indices and tables
+        Spi::run("CREATE TABLE test (id INTEGER, name TEXT) USING pgfdb_table").unwrap();
+        Spi::run("INSERT INTO test(id, name) VALUES (1, 'test1'), (2, 'test1'), (3, 'test2')")
+            .unwrap();
+
+        // Create index with existing rows to trigger index build that constructs index keys from table tuples
+        Spi::run("CREATE INDEX name_idx ON test USING pgfdb(name)").unwrap();
+        Spi::run("SET enable_seqscan=0").unwrap();
+
+        // Ensure a select will use our index
+        let explain = Spi::explain("SELECT count(*) FROM test WHERE name = 'test1';").unwrap();
+        assert!(
+            format!("{:?}", explain).contains("Index Name"),
+            "expected query plan to use index: {:?}",
+            explain.0.to_string()
+        );
+
+        // Ensure querying using the index returns the correct results
+        let result: Option<i64> =
+            Spi::get_one("SELECT count(*) FROM test WHERE name = 'test1'").unwrap();
+        assert_eq!(Some(2), result);
+    }
+
     #[pg_test]
-    fn select_eq_with_index() {
-        let cases = vec![
-            ("INTEGER", INTEGER_TEST_VALUES),
-            ("BIGINT", INTEGER_TEST_VALUES),
-            ("SMALLINT", INTEGER_TEST_VALUES),
-            ("REAL", FLOAT_TEST_VALUES),
-            ("FLOAT", FLOAT_TEST_VALUES),
-            ("TEXT", STRING_TEST_VALUES),
-            ("UUID", UUID_TEST_VALUES),
-        ];
+    fn select_eq_with_multi_column_index() {
+        Spi::run("CREATE TABLE test (id1 INTEGER, id2 INTEGER) USING pgfdb_table").unwrap();
+        Spi::run("CREATE INDEX id_idx ON test USING pgfdb(id1, id2)").unwrap();
+        Spi::run("SET enable_seqscan=0").unwrap();
 
-        for (column_type, (value1, value2, value3)) in cases {
-            let table = format!("test_{}", column_type.to_lowercase());
+        // Ensure a select on the initial column will use our index
+        let explain = Spi::explain("SELECT count(*) FROM test WHERE id1 = 1;").unwrap();
+        assert!(
+            format!("{:?}", explain).contains("Index Name"),
+            "expected query plan to use index: {:?}",
+            explain.0.to_string()
+        );
 
-            Spi::run(&format!(
-                "CREATE TABLE {table} (id {column_type}) USING pgfdb_table"
-            ))
+        // Ensure querying using the index returns the correct results
+        Spi::run("INSERT INTO test(id1, id2) VALUES (1, 1), (1, 2), (2, 1)").unwrap();
+        let result: Option<i64> = Spi::get_one("SELECT count(*) FROM test WHERE id1 = 1").unwrap();
+        assert_eq!(Some(2), result);
+
+        // Ensure a select on the second column will use our index with a skip scan
+        let explain = Spi::explain("SELECT count(*) FROM test WHERE id2 = 1").unwrap();
+        assert!(
+            format!("{:?}", explain).contains("Index Name"),
+            "expected query plan to use index: {:?}",
+            explain.0.to_string()
+        );
+        let result: Option<i64> = Spi::get_one("SELECT count(*) FROM test WHERE id2 = 1").unwrap();
+        assert_eq!(Some(2), result);
+
+        // Ensure a select on both columns will use our index
+        let explain = Spi::explain("SELECT count(*) FROM test WHERE id1 = 1 AND id2 = 2").unwrap();
+        assert!(
+            format!("{:?}", explain).contains("Index Name"),
+            "expected query plan to use index: {:?}",
+            explain.0.to_string()
+        );
+
+        // Ensure querying on both columns using the index returns the correct results
+        let result: Option<i64> =
+            Spi::get_one("SELECT count(*) FROM test WHERE id1 = 1 AND id2 = 2").unwrap();
+        assert_eq!(Some(1), result);
+
+        // Ensure an OR select on both columns will not use our index
+        let explain = Spi::explain("SELECT count(*) FROM test WHERE id1 = 1 OR id2 = 2").unwrap();
+        assert!(
+            !format!("{:?}", explain).contains("Index Name"),
+            "expected query plan to not use index: {:?}",
+            explain.0.to_string()
+        );
+    }
+
+    #[pg_test]
+    fn select_with_skip_scan() {
+        Spi::run("CREATE TABLE test (tenant INTEGER, email TEXT, age INTEGER) USING pgfdb_table")
             .unwrap();
-            Spi::run(&format!(
-                "CREATE INDEX {table}_id_idx ON {table} USING pgfdb(id)"
-            ))
+        Spi::run("CREATE INDEX tenant_email_idx ON test USING pgfdb(tenant, email, age)").unwrap();
+        Spi::run(
+            "INSERT INTO test(tenant, email, age)
+                SELECT i % 4, 'user' || (i % 10) || '@example.com', i FROM generate_series(1, 100) AS i",
+        )
+        .unwrap();
+        Spi::run("INSERT INTO test(tenant, email, age) VALUES (NULL, 'user1@example.com', 1)")
             .unwrap();
+        Spi::run("SET enable_seqscan=0").unwrap();
 
-            // Ensure the select will use our index
-            Spi::run("SET enable_seqscan=0").unwrap();
-            let explain = Spi::explain(&format!(
-                "SELECT count(*) FROM {table} WHERE id = CAST({value2} AS {column_type})"
-            ))
-            .unwrap();
+        let cases = vec![
+            (11, "email = 'user1@example.com'"),
+            (6, "email = 'user1@example.com' AND age < 50"),
+            (21, "email IN ('user1@example.com', 'user2@example.com')"),
+            (60, "email > 'user3@example.com'"),
+            (11, "age <= 10"),
+            (3, "tenant = 1 AND age < 12"),
+            (5, "tenant >= 2 AND email = 'user3@example.com'"),
+        ];
+
+        for (expected, condition) in cases {
+            let query = format!("SELECT count(*) FROM test WHERE {condition}");
+            let explain = Spi::explain(&query).unwrap();
             assert!(
                 format!("{:?}", explain).contains("Index Name"),
                 "expected query plan to use index: {:?}",
                 explain.0.to_string()
             );
 
-            // Ensure querying using the index returns the correct results
-            Spi::run(&format!(
-                "INSERT INTO {table}(id) VALUES ({value1}), ({value1}), ({value2}), ({value2}), ({value3}), ({value3})"
-            ))
-            .unwrap();
-            let result: Option<i64> = Spi::get_one(&format!(
-                "SELECT count(*) FROM {table} WHERE id = CAST({value2} as {column_type})"
-            ))
-            .unwrap();
-            assert_eq!(Some(2), result);
+            let result: Option<i64> = Spi::get_one(&query).unwrap();
+            assert_eq!(Some(expected), result, "unexpected result for {condition}");
         }
+
+        // Rows are returned in index order, grouped by the skipped column, in both directions
+        let result: Option<String> = Spi::get_one(
+            "SELECT string_agg(tenant || ':' || age, ',') FROM (
+                SELECT tenant, age FROM test WHERE email = 'user1@example.com' AND age > 1 AND age < 50
+                ORDER BY tenant, email, age
+            ) AS sorted",
+        )
+        .unwrap();
+        assert_eq!(Some("1:21,1:41,3:11,3:31".to_string()), result);
+
+        let result: Option<String> = Spi::get_one(
+            "SELECT string_agg(tenant || ':' || age, ',') FROM (
+                SELECT tenant, age FROM test WHERE email = 'user1@example.com' AND age > 1 AND age < 50
+                ORDER BY tenant DESC, email DESC, age DESC
+            ) AS sorted",
+        )
+        .unwrap();
+        assert_eq!(Some("3:31,3:11,1:41,1:21".to_string()), result);
     }
 
     #[pg_test]
-    fn select_not_eq_with_index() {
+    fn select_range_with_index() {
+        Spi::run("CREATE TABLE test (ts TIMESTAMP, value FLOAT) USING pgfdb_table").unwrap();
+        Spi::run("CREATE INDEX ts_idx ON test USING pgfdb(ts)").unwrap();
+        Spi::run("CREATE INDEX value_idx ON test USING pgfdb(value)").unwrap();
+        Spi::run(
+            "INSERT INTO test(ts, value) VALUES
+                ('2024-12-31 23:59:59', 1), ('2025-01-01', 2), ('2025-01-15', 2.5),
+                ('2025-01-31 23:59:59', 3), ('2025-02-01', 4), (NULL, NULL)",
+        )
+        .unwrap();
+        Spi::run("SET enable_seqscan=0").unwrap();
+
         let cases = vec![
-            ("INTEGER", INTEGER_TEST_VALUES),
-            ("BIGINT", INTEGER_TEST_VALUES),
-            ("SMALLINT", INTEGER_TEST_VALUES),
-            ("REAL", FLOAT_TEST_VALUES),
-            ("FLOAT", FLOAT_TEST_VALUES),
-            ("TEXT", STRING_TEST_VALUES),
-            ("UUID", UUID_TEST_VALUES),
+            (3, "ts >= '2025-01-01' AND ts < '2025-02-01'"),
+            (
+                2,
+                "ts > '2025-01-01' AND ts <= '2025-02-01' AND ts < '2025-02-01'",
+            ),
+            (
+                2,
+                "ts BETWEEN '2025-01-01' AND '2025-01-31' AND ts != '2025-01-15'",
+            ),
+            (1, "ts = '2025-01-15' AND ts > '2025-01-01'"),
+            (1, "value < 2.5 AND value > 1"),
+            (2, "value <= 2.5 AND value != 1 AND value != 3"),
+            // Contradictions which can't match any rows
+            (0, "value > 5 AND value < 3"),
+            (0, "value > 2 AND value < 2"),
+            (0, "value = 2 AND value > 2"),
+            (0, "value = 2 AND value != 2"),
+            (0, "value IS NULL AND value < 3"),
         ];
 
-        for (column_type, (value1, value2, value3)) in cases {
-            let table = format!("test_{}", column_type.to_lowercase());
+        for (expected, condition) in cases {
+            let query = format!("SELECT count(*) FROM test WHERE {condition}");
+            let explain = Spi::explain(&query).unwrap();
+            assert!(
+                format!("{:?}", explain).contains("Index Name"),
+                "expected query plan to use index: {:?}",
+                explain.0.to_string()
+            );
 
-            Spi::run(&format!(
-                "CREATE TABLE {table} (id {column_type}) USING pgfdb_table"
-            ))
-            .unwrap();
-            Spi::run(&format!(
-                "CREATE INDEX {table}_id_idx ON {table} USING pgfdb(id)"
-            ))
-            .unwrap();
+            let result: Option<i64> = Spi::get_one(&query).unwrap();
+            assert_eq!(Some(expected), result, "unexpected result for {condition}");
+        }
+    }
 
-            // Ensure the select will use our index
-            Spi::run("SET enable_seqscan=0").unwrap();
-            let explain = Spi::explain(&format!(
-                "SELECT count(*) FROM {table} WHERE id != CAST({value2} AS {column_type})"
-            ))
+    #[pg_test]
+    fn select_any_array_with_index() {
+        Spi::run("CREATE TABLE test (tenant INTEGER, id INTEGER) USING pgfdb_table").unwrap();
+        Spi::run("CREATE INDEX id_idx ON test USING pgfdb(id)").unwrap();
+        Spi::run("CREATE INDEX tenant_id_idx ON test USING pgfdb(tenant, id)").unwrap();
+        Spi::run("INSERT INTO test(tenant, id) SELECT i % 3, i FROM generate_series(1, 200) AS i")
             .unwrap();
+        Spi::run("SET enable_seqscan=0").unwrap();
+
+        let cases = vec![
+            (3, "id IN (5, 10, 15)"),
+            (3, "id = ANY(ARRAY[15, 5, 10, 5, NULL])"),
+            (0, "id = ANY(ARRAY[]::INTEGER[])"),
+            (100, "id = ANY(ARRAY(SELECT generate_series(1, 200, 2)))"),
+            (2, "id = ANY(ARRAY[5, 10, 15]) AND id > 5"),
+            (1, "id = ANY(ARRAY[5, 10, 15]) AND id = ANY(ARRAY[10, 20])"),
+            (2, "id = ANY(ARRAY[5, 10, 15]) AND id != 10"),
+            (0, "id = ANY(ARRAY[5, 10, 15]) AND id > 20"),
+            (9, "id < ANY(ARRAY[5, 10])"),
+            (3, "tenant = 1 AND id IN (1, 2, 4, 7)"),
+            (3, "tenant IN (0, 1) AND id IN (1, 2, 3, 4, 5)"),
+        ];
+
+        for (expected, condition) in cases {
+            let query = format!("SELECT count(*) FROM test WHERE {condition}");
+            let explain = Spi::explain(&query).unwrap();
             assert!(
                 format!("{:?}", explain).contains("Index Name"),
                 "expected query plan to use index: {:?}",
                 explain.0.to_string()
             );
 
-            // Ensure querying using the index returns the correct results
-            Spi::run(&format!(
-                "INSERT INTO {table}(id) VALUES ({value1}), ({value1}), ({value2}), ({value2}), ({value3}), ({value3})"
-            ))
-            .unwrap();
-            let result: Option<i64> = Spi::get_one(&format!(
-                "SELECT count(*) FROM {table} WHERE id != CAST({value2} as {column_type})"
-            ))
-            .unwrap();
-            assert_eq!(Some(4), result);
+            let result: Option<i64> = Spi::get_one(&query).unwrap();
+            assert_eq!(Some(expected), result, "unexpected result for {condition}");
         }
+
+        // Entries from the ranges of each value must be returned in index order in both directions
+        let result: Option<String> = Spi::get_one(
+            "SELECT string_agg(id::text, ',') FROM (SELECT id FROM test WHERE id IN (150, 3, 42, 7) ORDER BY id) AS sorted",
+        )
+        .unwrap();
+        assert_eq!(Some("3,7,42,150".to_string()), result);
+
+        let result: Option<String> = Spi::get_one(
+            "SELECT string_agg(id::text, ',') FROM (SELECT id FROM test WHERE id IN (150, 3, 42, 7) ORDER BY id DESC) AS sorted",
+        )
+        .unwrap();
+        assert_eq!(Some("150,42,7,3".to_string()), result);
     }
 
     #[pg_test]
-    fn select_lt_with_index() {
-        let cases = vec![
-            ("INTEGER", INTEGER_TEST_VALUES),
-            ("BIGINT", INTEGER_TEST_VALUES),
-            ("SMALLINT", INTEGER_TEST_VALUES),
-            ("REAL", FLOAT_TEST_VALUES),
-            ("FLOAT", FLOAT_TEST_VALUES),
-        ];
+    fn select_null_argument_with_index() {
+        Spi::run("CREATE TABLE test (id INTEGER) USING pgfdb_table").unwrap();
+        Spi::run("CREATE INDEX id_idx ON test USING pgfdb(id)").unwrap();
+        Spi::run("INSERT INTO test(id) SELECT i FROM generate_series(1, 100) AS i").unwrap();
+        Spi::run("INSERT INTO test(id) VALUES (NULL)").unwrap();
+        Spi::run("SET enable_seqscan=0").unwrap();
 
-        for (column_type, (value1, value2, value3)) in cases {
-            let table = format!("test_{}", column_type.to_lowercase());
+        let result: Option<i64> =
+            Spi::get_one("SELECT count(*) FROM test WHERE id = ANY(NULL::INTEGER[])").unwrap();
+        assert_eq!(Some(0), result);
 
-            Spi::run(&format!(
-                "CREATE TABLE {table} (id {column_type}) USING pgfdb_table"
-            ))
-            .unwrap();
-            Spi::run(&format!(
-                "CREATE INDEX {table}_id_idx ON {table} USING pgfdb(id)"
-            ))
+        // Generic plans pass the parameters as scan key arguments, which are NULL here
+        Spi::run("SET plan_cache_mode = force_generic_plan").unwrap();
+        Spi::run("PREPARE by_id(INTEGER) AS SELECT count(*) FROM test WHERE id = $1").unwrap();
+        Spi::run("PREPARE by_ids(INTEGER[]) AS SELECT count(*) FROM test WHERE id = ANY($1)")
             .unwrap();
 
-            // Ensure the select will use our index
-            Spi::run("SET enable_seqscan=0").unwrap();
-            let explain = Spi::explain(&format!(
-                "SELECT count(*) FROM {table} WHERE id < CAST({value2} AS {column_type})"
-            ))
-            .unwrap();
+        for query in ["EXECUTE by_id(NULL)", "EXECUTE by_ids(NULL)"] {
+            let explain = Spi::explain(query).unwrap();
             assert!(
                 format!("{:?}", explain).contains("Index Name"),
                 "expected query plan to use index: {:?}",
                 explain.0.to_string()
             );
 
-            // Ensure querying using the index returns the correct results
-            Spi::run(&format!(
-                "INSERT INTO {table}(id) VALUES ({value1}), ({value1}), ({value2}), ({value2}), ({value3}), ({value3})"
-            ))
-            .unwrap();
-            let result: Option<i64> = Spi::get_one(&format!(
-                "SELECT count(*) FROM {table} WHERE id < CAST({value2} AS {column_type})"
-            ))
-            .unwrap();
-            assert_eq!(Some(2), result);
+            let result: Option<i64> = Spi::get_one(query).unwrap();
+            assert_eq!(Some(0), result, "unexpected result for {query}");
         }
+
+        let result: Option<i64> = Spi::get_one("EXECUTE by_id(5)").unwrap();
+        assert_eq!(Some(1), result);
+        let result: Option<i64> = Spi::get_one("EXECUTE by_ids(ARRAY[5, NULL, 7])").unwrap();
+        assert_eq!(Some(2), result);
     }
 
     #[pg_test]
-    fn select_lte_with_index() {
+    fn select_inequality_with_multi_column_index() {
+        Spi::run("CREATE TABLE test (id1 INTEGER, id2 INTEGER, id3 INTEGER) USING pgfdb_table")
+            .unwrap();
+        Spi::run("CREATE INDEX id_idx ON test USING pgfdb(id1, id2, id3)").unwrap();
+        Spi::run(
+            "INSERT INTO test(id1, id2, id3) VALUES
+                (1, 1, 1), (1, 2, 1), (2, 1, 1), (2, 2, 2), (3, 1, 2), (3, 2, 1), (NULL, 1, 1)",
+        )
+        .unwrap();
+        Spi::run("SET enable_seqscan=0").unwrap();
+
         let cases = vec![
-            ("INTEGER", INTEGER_TEST_VALUES),
-            ("BIGINT", INTEGER_TEST_VALUES),
-            ("SMALLINT", INTEGER_TEST_VALUES),
-            ("REAL", FLOAT_TEST_VALUES),
-            ("FLOAT", FLOAT_TEST_VALUES),
+            // Inequality on the leading column, the remaining conditions are applied as a filter
+            (2, "id1 > 1 AND id2 = 1"),
+            (1, "id1 >= 2 AND id2 = 2 AND id3 = 1"),
+            (2, "id1 < 3 AND id2 != 1"),
+            (3, "id1 != 2 AND id3 = 1"),
+            (2, "id1 IS NOT NULL AND id2 = 1 AND id3 = 1"),
+            // Equality on the leading column followed by inequalities
+            (1, "id1 = 2 AND id2 > 1 AND id3 = 2"),
+            (1, "id1 = 3 AND id2 <= 1 AND id3 > 1"),
+            // Null conditions on the leading column
+            (1, "id1 IS NULL AND id2 = 1"),
         ];
 
-        for (column_type, (value1, value2, value3)) in cases {
-            let table = format!("test_{}", column_type.to_lowercase());
-
-            Spi::run(&format!(
-                "CREATE TABLE {table} (id {column_type}) USING pgfdb_table"
-            ))
-            .unwrap();
-            Spi::run(&format!(
-                "CREATE INDEX {table}_id_idx ON {table} USING pgfdb(id)"
-            ))
-            .unwrap();
-
-            // Ensure the select will use our index
-            Spi::run("SET enable_seqscan=0").unwrap();
-            let explain = Spi::explain(&format!(
-                "SELECT count(*) FROM {table} WHERE id <= CAST({value2} AS {column_type})"
-            ))
-            .unwrap();
+        for (expected, condition) in cases {
+            let query = format!("SELECT count(*) FROM test WHERE {condition}");
+            let explain = Spi::explain(&query).unwrap();
             assert!(
                 format!("{:?}", explain).contains("Index Name"),
                 "expected query plan to use index: {:?}",
                 explain.0.to_string()
             );
 
-            // Ensure querying using the index returns the correct results
-            Spi::run(&format!(
-                "INSERT INTO {table}(id) VALUES ({value1}), ({value1}), ({value2}), ({value2}), ({value3}), ({value3})"
-            ))
-            .unwrap();
-            let result: Option<i64> = Spi::get_one(&format!(
-                "SELECT count(*) FROM {table} WHERE id <= CAST({value2} AS {column_type})"
-            ))
+            let result: Option<i64> = Spi::get_one(&query).unwrap();
+            assert_eq!(Some(expected), result, "unexpected result for {condition}");
+        }
+    }
+
+    #[pg_test]
+    fn select_order_by_desc_with_index() {
+        Spi::run("CREATE TABLE test (id INTEGER) USING pgfdb_table").unwrap();
+        Spi::run("CREATE INDEX id_idx ON test USING pgfdb(id)").unwrap();
+        Spi::run("INSERT INTO test(id) VALUES (3), (1), (5), (2), (4)").unwrap();
+        Spi::run("SET enable_seqscan=0").unwrap();
+
+        // Ensure the ordering is served by scanning our index backwards
+        let query = "SELECT array_agg(id) FROM (SELECT id FROM test WHERE id > 1 ORDER BY id DESC LIMIT 3) t";
+        let explain = Spi::explain(query).unwrap();
+        assert!(
+            format!("{:?}", explain).contains("Backward"),
+            "expected query plan to use a backward index scan: {:?}",
+            explain.0.to_string()
+        );
+
+        let result: Option<Vec<i32>> = Spi::get_one(query).unwrap();
+        assert_eq!(Some(vec![5, 4, 3]), result);
+    }
+
+    #[pg_test]
+    fn select_order_by_with_desc_and_nulls_first_index() {
+        Spi::run("CREATE TABLE test (id INTEGER, score INTEGER) USING pgfdb_table").unwrap();
+        Spi::run("CREATE INDEX score_desc_idx ON test USING pgfdb(score DESC, id)").unwrap();
+        Spi::run(
+            "INSERT INTO test(id, score) VALUES (1, 10), (2, 30), (3, NULL), (4, 20), (5, 30)",
+        )
+        .unwrap();
+        Spi::run("SET enable_seqscan=0").unwrap();
+
+        // DESC columns default to NULLS FIRST, and the index should serve the mixed ordering without a sort
+        let cases = vec![
+            ("TRUE ORDER BY score DESC, id", "3,2,5,4,1"),
+            ("TRUE ORDER BY score, id DESC", "1,4,5,2,3"),
+            ("score > 15 ORDER BY score DESC, id", "2,5,4"),
+            ("score <= 20 ORDER BY score DESC, id", "4,1"),
+            ("score IS NULL ORDER BY score DESC, id", "3"),
+            ("score IS NOT NULL ORDER BY score DESC, id", "2,5,4,1"),
+        ];
+
+        for (condition, expected) in cases {
+            let query = format!(
+                "SELECT string_agg(id::text, ',') FROM (SELECT id FROM test WHERE {condition}) AS sorted"
+            );
+            let explain = format!("{:?}", Spi::explain(&query).unwrap());
+            assert!(
+                explain.contains("Index Name") && !explain.contains("Sort Key"),
+                "expected query plan to use index without sorting: {explain}"
+            );
+
+            let result: Option<String> = Spi::get_one(&query).unwrap();
+            assert_eq!(
+                Some(expected.to_string()),
+                result,
+                "unexpected result for {condition}"
+            );
+        }
+
+        Spi::run("DROP INDEX score_desc_idx").unwrap();
+        Spi::run("CREATE INDEX score_nulls_first_idx ON test USING pgfdb(score NULLS FIRST)")
             .unwrap();
-            assert_eq!(Some(4), result);
+
+        let cases = vec![
+            ("TRUE ORDER BY score NULLS FIRST", "null,10,20,30,30"),
+            ("TRUE ORDER BY score DESC NULLS LAST", "30,30,20,10,null"),
+            ("score >= 20 ORDER BY score NULLS FIRST", "20,30,30"),
+            ("score IS NULL ORDER BY score NULLS FIRST", "null"),
+        ];
+
+        for (condition, expected) in cases {
+            let query = format!(
+                "SELECT string_agg(coalesce(score::text, 'null'), ',') FROM (
+                    SELECT score FROM test WHERE {condition}
+                ) AS sorted"
+            );
+            let explain = format!("{:?}", Spi::explain(&query).unwrap());
+            assert!(
+                explain.contains("Index Name") && !explain.contains("Sort Key"),
+                "expected query plan to use index without sorting: {explain}"
+            );
+
+            let result: Option<String> = Spi::get_one(&query).unwrap();
+            assert_eq!(
+                Some(expected.to_string()),
+                result,
+                "unexpected result for {condition}"
+            );
         }
     }
 
     #[pg_test]
-    fn select_gt_with_index() {
-        let cases = vec![
-            ("INTEGER", INTEGER_TEST_VALUES),
-            ("BIGINT", INTEGER_TEST_VALUES),
-            ("SMALLINT", INTEGER_TEST_VALUES),
-            ("REAL", FLOAT_TEST_VALUES),
-            ("FLOAT", FLOAT_TEST_VALUES),
-        ];
+    fn ascending_columns_keep_plain_keys() {
+        use pollster::FutureExt;
 
-        for (column_type, (value1, value2, value3)) in cases {
-            let table = format!("test_{}", column_type.to_lowercase());
+        Spi::run("CREATE TABLE test (id INTEGER) USING pgfdb_table").unwrap();
+        Spi::run("CREATE INDEX id_idx ON test USING pgfdb(id)").unwrap();
+        Spi::run("INSERT INTO test(id) VALUES (1)").unwrap();
 
-            Spi::run(&format!(
-                "CREATE TABLE {table} (id {column_type}) USING pgfdb_table"
-            ))
+        let index_oid: pg_sys::Oid = Spi::get_one("SELECT 'id_idx'::regclass::oid")
+            .unwrap()
             .unwrap();
-            Spi::run(&format!(
-                "CREATE INDEX {table}_id_idx ON {table} USING pgfdb(id)"
-            ))
+        let row_id: i64 = Spi::get_one("SELECT (ctid::text::point)[0]::bigint FROM test")
+            .unwrap()
             .unwrap();
 
-            // Ensure the select will use our index
-            Spi::run("SET enable_seqscan=0").unwrap();
-            let explain = Spi::explain(&format!(
-                "SELECT count(*) FROM {table} WHERE id > CAST({value1} AS {column_type})"
-            ))
-            .unwrap();
-            assert!(
-                format!("{:?}", explain).contains("Index Name"),
-                "expected query plan to use index: {:?}",
-                explain.0.to_string()
-            );
+        // The default ordering stores the encoded values as they are, like before DESC columns were supported
+        let txn = crate::transaction::get_transaction();
+        let key = crate::subspace::index(index_oid).pack(&(1, row_id));
+        assert!(txn.get(&key, false).block_on().unwrap().is_some());
+    }
 
-            // Ensure querying using the index returns the correct results
-            Spi::run(&format!(
-                "INSERT INTO {table}(id) VALUES ({value1}), ({value1}), ({value2}), ({value2}), ({value3}), ({value3})"
-            ))
-            .unwrap();
-            let result: Option<i64> = Spi::get_one(&format!(
-                "SELECT count(*) FROM {table} WHERE id > CAST({value2} AS {column_type})"
-            ))
-            .unwrap();
-            assert_eq!(Some(2), result);
-        }
+    #[pg_test]
+    fn fetch_backward_from_cursor_with_index() {
+        Spi::run("CREATE TABLE test (id INTEGER) USING pgfdb_table").unwrap();
+        Spi::run("CREATE INDEX id_idx ON test USING pgfdb(id)").unwrap();
+        Spi::run("INSERT INTO test(id) VALUES (3), (1), (5), (2), (4)").unwrap();
+        Spi::run("SET enable_seqscan=0").unwrap();
+
+        Spi::run(
+            "DECLARE test_cursor SCROLL CURSOR FOR SELECT id FROM test WHERE id > 0 ORDER BY id",
+        )
+        .unwrap();
+
+        // Moving forwards and then backwards should continue from the current position
+        let result: Option<i32> = Spi::get_one("FETCH FORWARD 3 FROM test_cursor").unwrap();
+        assert_eq!(Some(1), result);
+        let result: Option<i32> = Spi::get_one("FETCH BACKWARD 1 FROM test_cursor").unwrap();
+        assert_eq!(Some(2), result);
+
+        // Running off the end and then moving backwards should return the last row
+        Spi::run("MOVE FORWARD ALL IN test_cursor").unwrap();
+        let result: Option<i32> = Spi::get_one("FETCH BACKWARD 1 FROM test_cursor").unwrap();
+        assert_eq!(Some(5), result);
     }
 
     #[pg_test]
-    fn select_gte_with_index() {
+    fn select_order_by_with_index() {
         let cases = vec![
-            ("INTEGER", INTEGER_TEST_VALUES),
-            ("BIGINT", INTEGER_TEST_VALUES),
-            ("SMALLINT", INTEGER_TEST_VALUES),
-            ("REAL", FLOAT_TEST_VALUES),
-            ("FLOAT", FLOAT_TEST_VALUES),
+            ("INTEGER", "(3), (-1), (2), (1)", "{-1,1,2,3}"),
+            ("BIGINT", "(3), (-1), (2), (1)", "{-1,1,2,3}"),
+            (
+                "FLOAT",
+                "('NaN'), ('Infinity'), (1.5), ('-Infinity'), (-2.5)",
+                "{-Infinity,-2.5,1.5,Infinity,NaN}",
+            ),
+            (
+                "UUID",
+                "('c552b673-47ba-4734-bd34-36905f1bf815'), ('00be8a3b-7747-4d96-a60e-0a0289825433'), ('573a831e-4c5a-4888-b98f-51f8e0017985')",
+                "{00be8a3b-7747-4d96-a60e-0a0289825433,573a831e-4c5a-4888-b98f-51f8e0017985,c552b673-47ba-4734-bd34-36905f1bf815}",
+            ),
         ];
 
-        for (column_type, (value1, value2, value3)) in cases {
+        for (column_type, values, expected) in cases {
             let table = format!("test_{}", column_type.to_lowercase());
 
             Spi::run(&format!(
@@ -480,167 +1773,115 @@ mod tests {
                 "CREATE INDEX {table}_id_idx ON {table} USING pgfdb(id)"
             ))
             .unwrap();
-
-            // Ensure the select will use our index
+            Spi::run(&format!("INSERT INTO {table}(id) VALUES {values}")).unwrap();
             Spi::run("SET enable_seqscan=0").unwrap();
-            let explain = Spi::explain(&format!(
-                "SELECT count(*) FROM {table} WHERE id >= CAST({value2} AS {column_type})"
-            ))
-            .unwrap();
+
+            // Ensure the ordering comes from our index rather than an explicit sort
+            let query = format!(
+                "SELECT array_agg(id)::text FROM (SELECT id FROM {table} WHERE id IS NOT NULL ORDER BY id) t"
+            );
+            let explain = Spi::explain(&query).unwrap();
             assert!(
-                format!("{:?}", explain).contains("Index Name"),
-                "expected query plan to use index: {:?}",
+                format!("{:?}", explain).contains("Index Name")
+                    && !format!("{:?}", explain).contains("Sort Key"),
+                "expected query plan to use index for ordering: {:?}",
                 explain.0.to_string()
             );
 
-            // Ensure querying using the index returns the correct results
-            Spi::run(&format!(
-                "INSERT INTO {table}(id) VALUES ({value1}), ({value1}), ({value2}), ({value2}), ({value3}), ({value3})"
-            ))
-            .unwrap();
-            let result: Option<i64> = Spi::get_one(&format!(
-                "SELECT count(*) FROM {table} WHERE id >= CAST({value2} AS {column_type})"
-            ))
-            .unwrap();
-            assert_eq!(Some(4), result);
+            let result: Option<String> = Spi::get_one(&query).unwrap();
+            assert_eq!(Some(expected.to_string()), result);
         }
     }
 
     #[pg_test]
-    fn select_lt_on_nulls_with_index() {
-        Spi::run("CREATE TABLE test (id INTEGER) USING pgfdb_table").unwrap();
-        Spi::run("CREATE INDEX id_idx ON test USING pgfdb(id)").unwrap();
-
-        // Ensure the select will use our index
+    fn select_order_by_with_nulls_in_index() {
+        Spi::run("CREATE TABLE test (a INTEGER, b INTEGER) USING pgfdb_table").unwrap();
+        Spi::run("CREATE INDEX test_a_b_idx ON test USING pgfdb(a, b)").unwrap();
+        Spi::run("INSERT INTO test(a, b) VALUES (1, 2), (1, NULL), (1, 1), (2, NULL), (2, 3)")
+            .unwrap();
         Spi::run("SET enable_seqscan=0").unwrap();
-        let explain = Spi::explain("SELECT count(*) FROM test WHERE id < 2;").unwrap();
-        assert!(
-            format!("{:?}", explain).contains("Index Name"),
-            "expected query plan to use index: {:?}",
-            explain.0.to_string()
-        );
-
-        // Ensure querying using the index does not count the NULL value
-        Spi::run("INSERT INTO test(id) VALUES (NULL), (1), (2), (3), (3)").unwrap();
-        let result: Option<i64> = Spi::get_one("SELECT count(*) FROM test WHERE id < 2").unwrap();
-        assert_eq!(Some(1), result);
-    }
 
-    #[pg_test]
-    fn select_null_with_index() {
-        Spi::run("CREATE TABLE test (id INTEGER) USING pgfdb_table").unwrap();
-        Spi::run("CREATE INDEX id_idx ON test USING pgfdb(id)").unwrap();
+        // NULLs sort last in ascending order and first in descending order
+        let cases = vec![
+            ("ORDER BY a, b", "{1,2,NULL}"),
+            ("ORDER BY a DESC, b DESC", "{NULL,2,1}"),
+        ];
 
-        // Ensure the select will use our index
-        Spi::run("SET enable_seqscan=0").unwrap();
-        let explain = Spi::explain("SELECT count(*) FROM test WHERE id IS NULL;").unwrap();
-        assert!(
-            format!("{:?}", explain).contains("Index Name"),
-            "expected query plan to use index: {:?}",
-            explain.0.to_string()
-        );
+        for (order_by, expected) in cases {
+            let query = format!(
+                "SELECT array_agg(b)::text FROM (SELECT b FROM test WHERE a = 1 {order_by}) t"
+            );
+            let explain = Spi::explain(&query).unwrap();
+            assert!(
+                format!("{:?}", explain).contains("Index Name")
+                    && !format!("{:?}", explain).contains("Sort Key"),
+                "expected query plan to use index for ordering: {:?}",
+                explain.0.to_string()
+            );
 
-        // Ensure querying using the index returns the correct results
-        Spi::run("INSERT INTO test(id) VALUES (1), (NULL), (NULL), (3)").unwrap();
-        let result: Option<i64> =
-            Spi::get_one("SELECT count(*) FROM test WHERE id IS NULL").unwrap();
-        assert_eq!(Some(2), result);
+            let result: Option<String> = Spi::get_one(&query).unwrap();
+            assert_eq!(Some(expected.to_string()), result);
+        }
     }
 
     #[pg_test]
-    fn select_not_null_with_index() {
-        Spi::run("CREATE TABLE test (id INTEGER) USING pgfdb_table").unwrap();
-        Spi::run("CREATE INDEX id_idx ON test USING pgfdb(id)").unwrap();
-
-        // Ensure the select will use our index
-        Spi::run("SET enable_seqscan=0").unwrap();
-        let explain = Spi::explain("SELECT count(*) FROM test WHERE id IS NOT NULL;").unwrap();
-        assert!(
-            format!("{:?}", explain).contains("Index Name"),
-            "expected query plan to use index: {:?}",
-            explain.0.to_string()
-        );
-
-        // Ensure querying using the index returns the correct results
-        Spi::run("INSERT INTO test(id) VALUES (1), (NULL), (NULL), (3)").unwrap();
-        let result: Option<i64> =
-            Spi::get_one("SELECT count(*) FROM test WHERE id IS NOT NULL").unwrap();
-        assert_eq!(Some(2), result);
-    }
+    fn merge_join_with_indexed_columns() {
+        Spi::run("CREATE TABLE orders (id INTEGER, customer_id INTEGER) USING pgfdb_table")
+            .unwrap();
+        Spi::run("CREATE TABLE customers (id INTEGER) USING pgfdb_table").unwrap();
+        Spi::run("CREATE INDEX orders_customer_idx ON orders USING pgfdb(customer_id)").unwrap();
+        Spi::run("CREATE INDEX customers_id_idx ON customers USING pgfdb(id)").unwrap();
 
-    #[pg_test]
-    fn select_eq_with_index_and_non_indexed_column() {
-        // Create a table with a non-indexed column first, followed by an indexed column
-        // This ensures we don't rely on column numbering between indices and tables
-        Spi::run("CREATE TABLE test (id INTEGER, name TEXT) USING pgfdb_table").unwrap();
-        Spi::run("INSERT INTO test(id, name) VALUES (1, 'test1'), (2, 'test1'), (3, 'test2')")
+        Spi::run("INSERT INTO customers (id) VALUES (1), (2), (3)").unwrap();
+        Spi::run("INSERT INTO orders (id, customer_id) VALUES (1, 1), (2, 1), (3, 3), (4, NULL)")
             .unwrap();
 
-        // Create index with existing rows to trigger index build that constructs index keys from table tuples
-        Spi::run("CREATE INDEX name_idx ON test USING pgfdb(name)").unwrap();
+        // Force a merge join, which should be able to use the ordering of both indexes without sorting
         Spi::run("SET enable_seqscan=0").unwrap();
+        Spi::run("SET enable_hashjoin=0").unwrap();
+        Spi::run("SET enable_nestloop=0").unwrap();
 
-        // Ensure a select will use our index
-        let explain = Spi::explain("SELECT count(*) FROM test WHERE name = 'test1';").unwrap();
+        // Our indexes can't yet be scanned without any conditions on them, so we add a filter to each
+        let query = "SELECT count(*) FROM orders o JOIN customers c ON o.customer_id = c.id WHERE o.customer_id > 0 AND c.id > 0";
+        let explain = Spi::explain(query).unwrap();
         assert!(
-            format!("{:?}", explain).contains("Index Name"),
-            "expected query plan to use index: {:?}",
+            format!("{:?}", explain).contains("Merge Join")
+                && !format!("{:?}", explain).contains("Sort Key"),
+            "expected query plan to merge join using indexes: {:?}",
             explain.0.to_string()
         );
 
-        // Ensure querying using the index returns the correct results
-        let result: Option<i64> =
-            Spi::get_one("SELECT count(*) FROM test WHERE name = 'test1'").unwrap();
-        assert_eq!(Some(2), result);
+        let result: Option<i64> = Spi::get_one(query).unwrap();
+        assert_eq!(Some(3), result);
     }
 
     #[pg_test]
-    fn select_eq_with_multi_column_index() {
-        Spi::run("CREATE TABLE test (id1 INTEGER, id2 INTEGER) USING pgfdb_table").unwrap();
-        Spi::run("CREATE INDEX id_idx ON test USING pgfdb(id1, id2)").unwrap();
-        Spi::run("SET enable_seqscan=0").unwrap();
-
-        // Ensure a select on the initial column will use our index
-        let explain = Spi::explain("SELECT count(*) FROM test WHERE id1 = 1;").unwrap();
-        assert!(
-            format!("{:?}", explain).contains("Index Name"),
-            "expected query plan to use index: {:?}",
-            explain.0.to_string()
-        );
+    fn merge_join_with_duplicate_keys() {
+        Spi::run("CREATE TABLE a (id INTEGER) USING pgfdb_table").unwrap();
+        Spi::run("CREATE TABLE b (id INTEGER) USING pgfdb_table").unwrap();
+        Spi::run("CREATE INDEX a_id_idx ON a USING pgfdb(id)").unwrap();
+        Spi::run("CREATE INDEX b_id_idx ON b USING pgfdb(id)").unwrap();
 
-        // Ensure querying using the index returns the correct results
-        Spi::run("INSERT INTO test(id1, id2) VALUES (1, 1), (1, 2), (2, 1)").unwrap();
-        let result: Option<i64> = Spi::get_one("SELECT count(*) FROM test WHERE id1 = 1").unwrap();
-        assert_eq!(Some(2), result);
+        Spi::run("INSERT INTO a (id) VALUES (1), (1), (2), (3), (3)").unwrap();
+        Spi::run("INSERT INTO b (id) VALUES (1), (1), (3), (3), (3)").unwrap();
 
-        // Ensure a select on the second column does not use index
-        let explain = Spi::explain("SELECT count(*) FROM test WHERE id2 = 1").unwrap();
-        assert!(
-            !format!("{:?}", explain).contains("Index Name"),
-            "expected query plan to not use index: {:?}",
-            explain.0.to_string()
-        );
+        Spi::run("SET enable_seqscan=0").unwrap();
+        Spi::run("SET enable_hashjoin=0").unwrap();
+        Spi::run("SET enable_nestloop=0").unwrap();
 
-        // Ensure a select on both columns will use our index
-        let explain = Spi::explain("SELECT count(*) FROM test WHERE id1 = 1 AND id2 = 2").unwrap();
+        // Duplicate keys on both sides make the merge join go back to the marked position on its inner side, which
+        // our index scans support without materializing the inner side
+        let query = "SELECT count(*) FROM a JOIN b ON a.id = b.id WHERE a.id > 0 AND b.id > 0";
+        let explain = Spi::explain(query).unwrap();
         assert!(
-            format!("{:?}", explain).contains("Index Name"),
-            "expected query plan to use index: {:?}",
+            format!("{:?}", explain).contains("Merge Join")
+                && !format!("{:?}", explain).contains("Materialize"),
+            "expected query plan to merge join without materializing: {:?}",
             explain.0.to_string()
         );
 
-        // Ensure querying on both columns using the index returns the correct results
-        let result: Option<i64> =
-            Spi::get_one("SELECT count(*) FROM test WHERE id1 = 1 AND id2 = 2").unwrap();
-        assert_eq!(Some(1), result);
-
-        // Ensure an OR select on both columns will not use our index
-        let explain = Spi::explain("SELECT count(*) FROM test WHERE id1 = 1 OR id2 = 2").unwrap();
-        assert!(
-            !format!("{:?}", explain).contains("Index Name"),
-            "expected query plan to not use index: {:?}",
-            explain.0.to_string()
-        );
+        let result: Option<i64> = Spi::get_one(query).unwrap();
+        assert_eq!(Some(10), result);
     }
 
     #[pg_test]
@@ -747,6 +1988,63 @@ mod tests {
         assert_eq!(Some(2), count, "Join with filter should return 2 rows");
     }
 
+    #[pg_test]
+    fn join_with_batched_index_lookups() {
+        Spi::run("CREATE TABLE categories (id INTEGER, name TEXT) USING pgfdb_table").unwrap();
+        Spi::run(
+            "CREATE TABLE products (id INTEGER, category_id INTEGER, price INTEGER) USING pgfdb_table",
+        )
+        .unwrap();
+        Spi::run("CREATE INDEX products_category_idx ON products USING pgfdb(category_id)")
+            .unwrap();
+
+        // Enough categories for several batches of outer rows, and products with NULL categories which never join
+        Spi::run(
+            "INSERT INTO categories SELECT i, 'category ' || i FROM generate_series(1, 250) AS i",
+        )
+        .unwrap();
+        Spi::run(
+            "INSERT INTO products SELECT i, CASE WHEN i % 10 = 0 THEN NULL ELSE i % 300 END, i
+            FROM generate_series(1, 3000) AS i",
+        )
+        .unwrap();
+
+        Spi::run("SET enable_seqscan=0").unwrap();
+        Spi::run("SET enable_hashjoin=0").unwrap();
+        Spi::run("SET enable_mergejoin=0").unwrap();
+
+        let query = "SELECT COUNT(*) FROM categories c JOIN products p ON p.category_id = c.id";
+        let explain = Spi::explain(query).unwrap();
+        assert!(
+            format!("{:?}", explain).contains("PgfdbBatchedNestLoop"),
+            "expected query plan to use a batched nested loop: {:?}",
+            explain.0.to_string()
+        );
+
+        let expected = (1..=3000)
+            .filter(|i| i % 10 != 0 && (1..=250).contains(&(i % 300)))
+            .count() as i64;
+        assert_eq!(Some(expected), Spi::get_one::<i64>(query).unwrap());
+
+        // Every product must be joined with its own category
+        let mismatched: Option<i64> = Spi::get_one(
+            "SELECT COUNT(*) FROM categories c JOIN products p ON p.category_id = c.id
+            WHERE c.name <> 'category ' || p.category_id",
+        )
+        .unwrap();
+        assert_eq!(Some(0), mismatched);
+
+        // Filters on the inner table are applied to the joined rows
+        let expected = (1501..=3000)
+            .filter(|i| i % 10 != 0 && (1..=250).contains(&(i % 300)))
+            .count() as i64;
+        let count: Option<i64> = Spi::get_one(
+            "SELECT COUNT(*) FROM categories c JOIN products p ON p.category_id = c.id WHERE p.price > 1500",
+        )
+        .unwrap();
+        assert_eq!(Some(expected), count);
+    }
+
     #[pg_test]
     fn join_with_multiple_conditions() {
         Spi::run("CREATE TABLE employees (id INTEGER PRIMARY KEY, dept_id INTEGER, manager_id INTEGER) USING pgfdb_table").unwrap();
@@ -814,6 +2112,125 @@ mod tests {
             assert_eq!(Some(*expected), result);
         }
     }
+
+    // Creates a table with an index on an `id` column of the given type and checks that a scan comparing it to the
+    // second of the test values uses the index and counts the expected rows, with each test value inserted twice
+    fn assert_index_scan_count(
+        column_type: &str,
+        (value1, value2, value3): (&str, &str, &str),
+        operator: &str,
+        expected: i64,
+    ) {
+        let table = format!(
+            "test_{}",
+            column_type
+                .to_lowercase()
+                .replace(|c: char| !c.is_ascii_alphanumeric(), "")
+        );
+
+        Spi::run(&format!(
+            "CREATE TABLE {table} (id {column_type}) USING pgfdb_table"
+        ))
+        .unwrap();
+        Spi::run(&format!(
+            "CREATE INDEX {table}_id_idx ON {table} USING pgfdb(id)"
+        ))
+        .unwrap();
+
+        // Ensure the select will use our index
+        let query = format!(
+            "SELECT count(*) FROM {table} WHERE id {operator} CAST({value2} AS {column_type})"
+        );
+        Spi::run("SET enable_seqscan=0").unwrap();
+        let explain = Spi::explain(&query).unwrap();
+        assert!(
+            format!("{:?}", explain).contains("Index Name"),
+            "expected query plan to use index: {:?}",
+            explain.0.to_string()
+        );
+
+        // Ensure querying using the index returns the correct results
+        Spi::run(&format!(
+            "INSERT INTO {table}(id) VALUES ({value1}), ({value1}), ({value2}), ({value2}), ({value3}), ({value3})"
+        ))
+        .unwrap();
+        let result: Option<i64> = Spi::get_one(&query).unwrap();
+        assert_eq!(Some(expected), result, "{column_type} {operator}");
+    }
+
+    // Commits what the session transaction has written to FDB so far, which makes it visible to work done in
+    // separate transactions, like online index builds. The Postgres transaction of a test is always rolled back, so
+    // tables with committed rows have to be cleared again with `clear_committed_table`.
+    fn commit_session_transaction() {
+        unsafe {
+            crate::transaction::transaction_callback(
+                pg_sys::XactEvent::XACT_EVENT_COMMIT,
+                std::ptr::null_mut(),
+            );
+        }
+    }
+
+    fn abort_session_transaction() {
+        unsafe {
+            crate::transaction::transaction_callback(
+                pg_sys::XactEvent::XACT_EVENT_ABORT,
+                std::ptr::null_mut(),
+            );
+        }
+    }
+
+    // Reads the relfilenumber an index is currently stored under
+    fn index_relfilenumber(index: &str) -> pg_sys::RelFileNumber {
+        Spi::get_one(&format!(
+            "SELECT relfilenode FROM pg_class WHERE oid = '{index}'::regclass"
+        ))
+        .unwrap()
+        .unwrap()
+    }
+
+    // Clears the rows and index entries of a table from FDB. Indexes which have been rebuilt can have entries under
+    // their OID as well as their current relfilenumber.
+    fn clear_committed_table(table: &str) {
+        let table_oid: pg_sys::Oid = Spi::get_one(&format!("SELECT '{table}'::regclass::oid"))
+            .unwrap()
+            .unwrap();
+        let relfilenumbers: Vec<pg_sys::RelFileNumber> = Spi::get_one(&format!(
+            "SELECT array_agg(c.oid) || array_agg(c.relfilenode)
+            FROM pg_index i JOIN pg_class c ON c.oid = i.indexrelid
+            WHERE i.indrelid = '{table}'::regclass"
+        ))
+        .unwrap()
+        .unwrap_or_default();
+
+        crate::transaction::run_in_separate_transaction(|txn| {
+            txn.clear_subspace_range(&crate::subspace::table(table_oid));
+            for relfilenumber in &relfilenumbers {
+                txn.clear_subspace_range(&crate::subspace::index(*relfilenumber));
+                txn.clear(crate::subspace::index_state(*relfilenumber).bytes());
+            }
+            Ok(())
+        });
+    }
+
+    // Reads the number of entries and the build state of an index as committed to FDB, given the relfilenumber it is
+    // stored under, which is the OID of indexes that haven't been rebuilt
+    fn committed_index_contents(
+        relfilenumber: pg_sys::RelFileNumber,
+    ) -> (usize, Option<crate::iam::state::IndexState>) {
+        use foundationdb::RangeOption;
+        use futures::TryStreamExt;
+        use pollster::FutureExt;
+
+        crate::transaction::run_in_separate_transaction(|txn| {
+            let range_option = RangeOption::from(crate::subspace::index(relfilenumber).range());
+            let entries = txn
+                .get_ranges_keyvalues(range_option, false)
+                .try_fold(0, |entries, _| async move { Ok(entries + 1) })
+                .block_on()?;
+            let state = crate::iam::state::get(txn, relfilenumber, false)?;
+            Ok((entries, state))
+        })
+    }
 }
 
 /// This module is required by `cargo pgrx test` invocations.
@@ -1,12 +1,20 @@
-use foundationdb::tuple::{pack, Subspace};
-use pgrx::pg_sys::Oid;
+use foundationdb::tuple::{Subspace, pack};
+use pgrx::pg_sys::{Oid, RelFileNumber};
 
 pub fn table(oid: Oid) -> Subspace {
     let prefix = pack(&("tables", oid.to_u32()));
     Subspace::from_bytes(prefix)
 }
 
-pub fn index(oid: Oid) -> Subspace {
-    let prefix = pack(&("indexes", oid.to_u32()));
+// Indexes are stored under their relfilenumber, which starts out as the OID of the index. REINDEX gives an index a
+// new relfilenumber, so the rebuilt index is written next to the old one, which stays in use until the transaction
+// commits and its keys are cleared (see `transaction::clear_replaced_indexes`).
+pub fn index(relfilenumber: RelFileNumber) -> Subspace {
+    let prefix = pack(&("indexes", relfilenumber.to_u32()));
+    Subspace::from_bytes(prefix)
+}
+
+pub fn index_state(relfilenumber: RelFileNumber) -> Subspace {
+    let prefix = pack(&("index_states", relfilenumber.to_u32()));
     Subspace::from_bytes(prefix)
 }
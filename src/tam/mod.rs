@@ -2,8 +2,9 @@ use std::ptr::addr_of_mut;
 
 mod scan;
 
+use foundationdb::{FdbResult, Transaction};
 use pgrx::{
-    PgBox,
+    PgBox, PgLogLevel,
     callconv::BoxRet,
     itemptr::{item_pointer_get_block_number_no_check, item_pointer_set_all},
     list::List,
@@ -303,6 +304,7 @@ unsafe extern "C-unwind" fn tuple_insert(
         let key = subspace::table((*rel).rd_id).pack(&id);
         let txn = crate::transaction::get_transaction();
         txn.set(&key, &encoded);
+        crate::transaction::record_write((*rel).rd_id);
     }
 }
 
@@ -363,50 +365,62 @@ unsafe extern "C-unwind" fn tuple_delete(
 
         // Get the tuple data before deleting it
         if let Some(value) = txn.get(&key, false).block_on().unwrap_or_pg_error() {
-            // Decode the tuple
-            let mut tuple = crate::coding::Tuple::deserialize(&value);
+            clear_index_keys(rel, id, &value);
+        }
 
-            // Create a tuple table slot for the heap tuple
-            let tuple_desc = (*rel).rd_att;
-            let table_slot = pg_sys::MakeSingleTupleTableSlot(tuple_desc, &pg_sys::TTSOpsVirtual);
+        // Now delete the tuple itself
+        txn.clear(&key);
+        crate::transaction::record_write((*rel).rd_id);
 
-            // Load the tuple into the slot
-            tuple.load_into_tts(table_slot.as_mut().unwrap());
+        // For some reason this is not counting correctly how many tuples have been removed
+        // The response after running a delete is always "DELETE 0"
+        TM_Result::TM_Deleted
+    }
+}
 
-            // Get all indexes on this relation
-            current_context(|ctx| {
-                let index_oids: List<Oid> =
-                    List::downcast_ptr_in_memcx((*rel).rd_indexlist, ctx).unwrap();
+// Clears the keys of a stored tuple from all indexes on the relation
+unsafe fn clear_index_keys(rel: Relation, id: u32, value: &[u8]) {
+    unsafe {
+        let txn = crate::transaction::get_transaction();
 
-                for index_oid in index_oids.iter() {
-                    let index_rel = RelationIdGetRelation(*index_oid);
+        // Decode the tuple
+        let mut tuple = crate::coding::Tuple::deserialize(value);
 
-                    if !index_rel.is_null() {
-                        // Create index info
-                        let index_info = pg_sys::BuildIndexInfo(index_rel);
+        // Create a tuple table slot for the heap tuple
+        let tuple_desc = (*rel).rd_att;
+        let table_slot = pg_sys::MakeSingleTupleTableSlot(tuple_desc, &pg_sys::TTSOpsVirtual);
 
-                        // Build and clear the index key
-                        let key = crate::iam::build::build_key_from_table_tuple(
-                            *index_oid, id, index_rel, table_slot, index_info,
-                        );
-                        txn.clear(&key);
+        // Load the tuple into the slot
+        tuple.load_into_tts(table_slot.as_mut().unwrap());
 
-                        // Free index info and index relation
-                        RelationClose(index_rel);
-                    }
-                }
-            });
+        // Get all indexes on this relation
+        current_context(|ctx| {
+            let index_oids: List<Oid> =
+                List::downcast_ptr_in_memcx((*rel).rd_indexlist, ctx).unwrap();
 
-            // Free the heap slot
-            pg_sys::ExecDropSingleTupleTableSlot(table_slot);
-        }
+            for index_oid in index_oids.iter() {
+                let index_rel = RelationIdGetRelation(*index_oid);
 
-        // Now delete the tuple itself
-        txn.clear(&key);
+                if !index_rel.is_null() {
+                    // Create index info
+                    let index_info = pg_sys::BuildIndexInfo(index_rel);
 
-        // For some reason this is not counting correctly how many tuples have been removed
-        // The response after running a delete is always "DELETE 0"
-        TM_Result::TM_Deleted
+                    // Build and clear the index key. Rows which don't match the predicate of a partial index
+                    // never had a key in it.
+                    if let Some(key) = crate::iam::build::build_key_from_table_tuple(
+                        id, index_rel, table_slot, index_info,
+                    ) {
+                        txn.clear(&key);
+                    }
+
+                    // Free index info and index relation
+                    RelationClose(index_rel);
+                }
+            }
+        });
+
+        // Free the heap slot
+        pg_sys::ExecDropSingleTupleTableSlot(table_slot);
     }
 }
 
@@ -432,7 +446,15 @@ unsafe extern "C-unwind" fn tuple_update(
 
         let key = subspace::table((*rel).rd_id).pack(&id);
         let txn = crate::transaction::get_transaction();
+
+        // Clear the index keys of the old version of the tuple. The new keys are inserted by Postgres afterwards,
+        // but only for indexes whose predicate the new version matches.
+        if let Some(value) = txn.get(&key, false).block_on().unwrap_or_pg_error() {
+            clear_index_keys(rel, id, &value);
+        }
+
         txn.set(&key, &encoded);
+        crate::transaction::record_write((*rel).rd_id);
 
         // Store back the old TID as the new one as we don't handle visibility checks and don't need new IDs
         (*slot).tts_tid = *otid;
@@ -519,12 +541,29 @@ unsafe extern "C-unwind" fn relation_copy_for_cluster(
 ) {
 }
 
+// Table rows are deleted right away, so there are no dead rows to clean up. Indexes can still be left with entries
+// pointing to deleted rows though, which are cleared here unless index cleanup is disabled. We also store the size
+// of the table for the planner.
 #[pg_guard]
 unsafe extern "C-unwind" fn relation_vacuum(
-    _rel: Relation,
-    _params: *mut VacuumParams,
-    _bstrategy: BufferAccessStrategy,
+    rel: Relation,
+    params: *mut VacuumParams,
+    bstrategy: BufferAccessStrategy,
 ) {
+    unsafe {
+        update_table_stats(rel);
+
+        if (*params).index_cleanup == pg_sys::VacOptValue::VACOPTVALUE_DISABLED {
+            return;
+        }
+
+        let log_level = if (*params).options & pg_sys::VACOPT_VERBOSE != 0 {
+            PgLogLevel::INFO
+        } else {
+            PgLogLevel::DEBUG2
+        };
+        crate::iam::vacuum::vacuum_indexes(rel, log_level, bstrategy);
+    }
 }
 
 #[pg_guard]
@@ -567,12 +606,19 @@ unsafe extern "C-unwind" fn index_build_range_scan(
 
 #[pg_guard]
 unsafe extern "C-unwind" fn index_validate_scan(
-    _table_rel: Relation,
-    _index_rel: Relation,
-    _index_info: *mut IndexInfo,
+    table_rel: Relation,
+    index_rel: Relation,
+    index_info: *mut IndexInfo,
     _snapshot: Snapshot,
-    _state: *mut ValidateIndexState,
+    state: *mut ValidateIndexState,
 ) {
+    unsafe {
+        log!("TAM: Validate index");
+
+        // `CREATE INDEX CONCURRENTLY` only makes writers maintain the index after its initial build, so the index
+        // is built once more to pick up rows written in the meantime
+        (*state).htups = crate::iam::build::validate_online(table_rel, index_rel, index_info);
+    }
 }
 
 #[pg_guard]
@@ -695,30 +741,95 @@ unsafe extern "C-unwind" fn relation_size(
     0
 }
 
+// Minimum number of pages we assume a table holds. Like Postgres does for heap tables which have never been
+// vacuumed, we don't trust estimates of (nearly) empty tables as they might have been filled since FDB last sampled
+// them, and underestimating the size can lead to much worse plans than overestimating it.
+const MIN_ESTIMATED_PAGES: f64 = 10.0;
+
+// Estimates the size of a table for the planner from the statistics `VACUUM` stores in `pg_class` (see
+// `update_table_stats`), so planning doesn't have to wait for FDB. Tables which haven't been vacuumed yet are assumed
+// to hold `MIN_ESTIMATED_PAGES` pages worth of rows. `ANALYZE` can't sample our tables and stores zero rows, which
+// we treat the same way.
 #[pg_guard]
 unsafe extern "C-unwind" fn relation_estimate_size(
     rel: Relation,
     _attr_widths: *mut int32,
-    _pages: *mut BlockNumber,
-    _tuples: *mut f64,
-    _allvisfrac: *mut f64,
+    pages: *mut BlockNumber,
+    tuples: *mut f64,
+    allvisfrac: *mut f64,
 ) {
     unsafe {
+        let relpages = (*(*rel).rd_rel).relpages;
+        let reltuples = (*(*rel).rd_rel).reltuples as f64;
+        if relpages > 0 && reltuples > 0.0 {
+            *pages = relpages as BlockNumber;
+            *tuples = reltuples;
+        } else {
+            let num_bytes = MIN_ESTIMATED_PAGES * pg_sys::BLCKSZ as f64;
+            *pages = MIN_ESTIMATED_PAGES as BlockNumber;
+            *tuples = (num_bytes / estimate_row_bytes(rel)).round();
+        }
         log!(
-            "Estimate relation size, previous estimate {}",
-            (*(*rel).rd_rel).reltuples
+            "Estimate relation size, {} rows and {} pages",
+            *tuples,
+            *pages
         );
 
-        // let data_width = get_relation_data_width((*rel).rd_id, attr_widths) as i64;
-        // let estimated_size = database::get_size_estimate_for_table(rel);
-        // log!(
-        //     "Data width {}, estimated size {}",
-        //     data_width,
-        //     estimated_size
-        // );
+        // We don't do any visibility checks
+        *allvisfrac = 1.0;
+    }
+}
 
-        // *pages = 1;
-        // *tuples = (estimated_size / data_width) as f64;
-        // *allvisfrac = 1.0;
+// Stores the estimated size of a table in `pg_class` for `relation_estimate_size`. Rows are stored as individual
+// keys rather than in pages, but the planner bases the cost of sequential scans on pages, so we store how many pages
+// worth of data the table holds.
+unsafe fn update_table_stats(rel: Relation) {
+    unsafe {
+        let (num_rows, num_bytes) =
+            crate::transaction::run_in_separate_transaction(|txn| estimate_table_size(txn, rel));
+        let num_pages = (num_bytes / pg_sys::BLCKSZ as f64).ceil() as BlockNumber;
+
+        // Invalid transaction IDs leave the frozen IDs of the table as they are
+        pg_sys::vac_update_relstats(
+            rel,
+            num_pages,
+            num_rows,
+            num_pages,
+            (*(*rel).rd_rel).relhasindex,
+            TransactionId::INVALID,
+            MultiXactId::INVALID,
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+            false,
+        );
+    }
+}
+
+// Estimates the number of rows and bytes in a table. Only the size estimate FDB keeps from sampling its storage is
+// used, which doesn't require reading any rows, and the number of rows is derived from it using the width of the
+// table's columns, just like Postgres does for heap pages. The estimate doesn't include recent or uncommitted
+// writes, so small tables are assumed to hold a few pages worth of rows (see `MIN_ESTIMATED_PAGES`).
+pub(crate) unsafe fn estimate_table_size(
+    txn: &Transaction,
+    rel: Relation,
+) -> FdbResult<(f64, f64)> {
+    unsafe {
+        let table_subspace = subspace::table((*rel).rd_id);
+        let (start, end) = table_subspace.range();
+        let estimated_bytes = txn
+            .get_estimated_range_size_bytes(&start, &end)
+            .block_on()? as f64;
+        let num_bytes = estimated_bytes.max(MIN_ESTIMATED_PAGES * pg_sys::BLCKSZ as f64);
+
+        Ok(((num_bytes / estimate_row_bytes(rel)).round(), num_bytes))
+    }
+}
+
+// Estimates the number of bytes a row of a table takes up in FDB. Each row is stored under its own key, made up of
+// the table subspace and the row ID.
+unsafe fn estimate_row_bytes(rel: Relation) -> f64 {
+    unsafe {
+        let key_bytes = subspace::table((*rel).rd_id).pack(&0u32).len() as f64;
+        key_bytes + pg_sys::get_rel_data_width(rel, std::ptr::null_mut()) as f64
     }
 }
@@ -1,22 +1,50 @@
 use std::sync::OnceLock;
 
-use foundationdb::{options::TransactionOption, Transaction};
-use pg_sys::XactEvent;
+use foundationdb::{
+    FdbResult, KeySelector, RangeOption, Transaction,
+    future::FdbValues,
+    options::{StreamingMode, TransactionOption},
+    tuple::{Element, pack, unpack},
+};
+use futures::future::join_all;
+use pg_sys::{Oid, RelFileLocator, RelFileNumber, XactEvent};
 use pgrx::{pg_sys::panic::ErrorReportable, prelude::*};
 use pollster::FutureExt;
 
+use crate::errors::FdbErrorExt;
+
 // Not sure how well this will work with multiple connections at the same time
 // Perhaps thread_local! can be used instead, although lifetimes are more painful using that
 static mut TRANSACTION: OnceLock<Transaction> = OnceLock::new();
 
+// Tables which have been written to in the current transaction. Those writes are only visible to the session
+// transaction until it commits, which matters for work done in separate transactions, like online index builds.
+static mut WRITTEN_TABLES: Vec<Oid> = Vec::new();
+
+// Read version the session transaction has been set to use, see `use_read_version`
+static mut READ_VERSION: Option<i64> = None;
+
+// Relfilenumbers of indexes created or rebuilt in the current transaction which have been built online. Those builds
+// commit their work in separate transactions, so if the current transaction aborts and the storage of the index is
+// dropped again, the keys and build state they left behind have to be cleared, see `abort_transaction`.
+static mut ONLINE_BUILDS: Vec<RelFileNumber> = Vec::new();
+
+// Whether an index has been rebuilt into new storage in the current transaction, see `clear_replaced_indexes`
+static mut HAS_REBUILT: bool = false;
+
 #[pg_guard]
 pub unsafe extern "C-unwind" fn transaction_callback(
     event: u32,
     _arg: *mut ::std::os::raw::c_void,
 ) {
     match event {
+        XactEvent::XACT_EVENT_PRE_COMMIT => clear_replaced_indexes(),
         XactEvent::XACT_EVENT_COMMIT => commit_transaction(),
         XactEvent::XACT_EVENT_ABORT => abort_transaction(),
+        // Parallel workers only read, so their transactions are just dropped
+        XactEvent::XACT_EVENT_PARALLEL_COMMIT | XactEvent::XACT_EVENT_PARALLEL_ABORT => {
+            abort_transaction()
+        }
         _ => (),
     }
     log!("TXN: Tranasction callback for event {}", event);
@@ -40,7 +68,241 @@ pub fn get_transaction() -> &'static Transaction {
     }
 }
 
+pub fn record_write(table_oid: Oid) {
+    #[allow(static_mut_refs)]
+    unsafe {
+        if !WRITTEN_TABLES.contains(&table_oid) {
+            WRITTEN_TABLES.push(table_oid);
+        }
+    }
+}
+
+pub fn has_written(table_oid: Oid) -> bool {
+    #[allow(static_mut_refs)]
+    unsafe {
+        WRITTEN_TABLES.contains(&table_oid)
+    }
+}
+
+pub fn has_any_writes() -> bool {
+    #[allow(static_mut_refs)]
+    unsafe {
+        !WRITTEN_TABLES.is_empty()
+    }
+}
+
+pub fn record_online_build(relfilenumber: RelFileNumber) {
+    #[allow(static_mut_refs)]
+    unsafe {
+        if !ONLINE_BUILDS.contains(&relfilenumber) {
+            ONLINE_BUILDS.push(relfilenumber);
+        }
+    }
+}
+
+pub fn record_rebuild() {
+    unsafe {
+        HAS_REBUILT = true;
+    }
+}
+
+// Makes the session transaction read at the given version. Parallel workers use this to read the same data as the
+// leader, which has to happen before their transaction is used for anything else.
+pub fn use_read_version(version: i64) {
+    #[allow(static_mut_refs)]
+    unsafe {
+        if READ_VERSION != Some(version) {
+            get_transaction().set_read_version(version);
+            READ_VERSION = Some(version);
+        }
+    }
+}
+
+// Runs a function in a new transaction, separate from the session transaction, and commits it. This is used for
+// work which is too large for a single transaction and is split into batches. The function is retried on errors
+// that FDB considers retryable, such as conflicts with concurrent writes, so it must be safe to run more than once.
+pub fn run_in_separate_transaction<T>(f: impl Fn(&Transaction) -> FdbResult<T>) -> T {
+    try_run_in_separate_transaction(f).unwrap_or_pg_error()
+}
+
+// Like `run_in_separate_transaction`, but returns errors which can't be retried instead of raising them. Like the
+// session transaction, the transaction times out after 5 seconds, which includes its retries, so work that keeps
+// failing with retryable errors gives up rather than retrying forever.
+fn try_run_in_separate_transaction<T>(f: impl Fn(&Transaction) -> FdbResult<T>) -> FdbResult<T> {
+    let db = foundationdb::Database::default()?;
+    let mut txn = db.create_trx()?;
+    txn.set_option(TransactionOption::Timeout(5_000))?;
+
+    loop {
+        match f(&txn) {
+            Ok(value) => match txn.commit().block_on() {
+                Ok(_) => return Ok(value),
+                Err(err) => txn = err.on_error().block_on()?,
+            },
+            Err(err) => txn = txn.on_error(err).block_on()?,
+        }
+    }
+}
+
+// Runs a function over all key-values in a range of keys which belong to a table, like the table itself or one of
+// its indexes, in batches of up to `batch_size` and returns the result for each batch. Every batch runs in a
+// separate transaction, so the work isn't limited by FDB's 5 second limit. If the session transaction has written
+// to the table, those writes wouldn't be visible to other transactions, so the session transaction is used for all
+// batches instead.
+pub fn run_in_batches<T>(
+    table_oid: Oid,
+    (start, end): (Vec<u8>, Vec<u8>),
+    batch_size: usize,
+    f: impl Fn(&Transaction, &FdbValues) -> FdbResult<T>,
+) -> Vec<T> {
+    let in_session = has_written(table_oid);
+    let mut results = Vec::new();
+    let mut cursor: Option<Vec<u8>> = None;
+
+    loop {
+        let run_batch = |txn: &Transaction| -> FdbResult<_> {
+            let begin = match &cursor {
+                Some(cursor) => KeySelector::first_greater_than(cursor.clone()),
+                None => KeySelector::first_greater_or_equal(start.clone()),
+            };
+            let range_option = RangeOption {
+                begin,
+                end: KeySelector::first_greater_or_equal(end.clone()),
+                limit: Some(batch_size),
+                mode: StreamingMode::WantAll,
+                ..RangeOption::from((start.clone(), end.clone()))
+            };
+            let values = txn.get_range(&range_option, 1, false).block_on()?;
+
+            let result = f(txn, &values)?;
+            let last = values.last().map(|value| value.key().to_vec());
+            Ok((result, last, !values.more()))
+        };
+
+        let (result, last, done) = if in_session {
+            run_batch(get_transaction()).unwrap_or_pg_error()
+        } else {
+            run_in_separate_transaction(run_batch)
+        };
+
+        results.push(result);
+        if done || last.is_none() {
+            return results;
+        }
+        cursor = last;
+    }
+}
+
+// Target size in bytes of the chunks that parallel scans and index builds split their ranges of keys into. FDB
+// picks the split points so that the chunks are roughly this size.
+const PARALLEL_CHUNK_BYTES: i64 = 1_000_000;
+
+// Where a list of key ranges is cut into chunks, as the index of a range and, unless the cut is at the start of
+// that range, a key within it. Each chunk spans from one cut to the next.
+pub type Cut = (usize, Option<Vec<u8>>);
+
+// Splits a list of key ranges into chunks for parallel workers at the split points FDB picks for them. The cuts
+// between the chunks are returned along with their packed form, which is shared with the workers and fits within
+// `max_size` bytes. If there are too many cuts to fit, pairs of chunks are merged.
+pub fn plan_chunks(
+    txn: &Transaction,
+    ranges: &[RangeOption<'static>],
+    max_size: usize,
+) -> FdbResult<(Vec<Cut>, Vec<u8>)> {
+    let split_points = join_all(ranges.iter().map(|range| {
+        txn.get_range_split_points(range.begin.key(), range.end.key(), PARALLEL_CHUNK_BYTES)
+    }))
+    .block_on();
+
+    let mut cuts = Vec::new();
+    for (index, (range, points)) in ranges.iter().zip(split_points).enumerate() {
+        cuts.push((index, None));
+
+        // The split points include the start and end of the range, which are cuts already
+        for point in points?.iter() {
+            let key = point.key();
+            if key > range.begin.key() && key < range.end.key() {
+                cuts.push((index, Some(key.to_vec())));
+            }
+        }
+    }
+    cuts.push((ranges.len(), None));
+
+    let mut packed = pack_cuts(&cuts);
+    while packed.len() > max_size {
+        // Merge pairs of chunks by dropping every other cut, always keeping the first and last
+        let last = cuts.len() - 1;
+        cuts = cuts
+            .into_iter()
+            .enumerate()
+            .filter(|(index, _)| index % 2 == 0 || *index == last)
+            .map(|(_, cut)| cut)
+            .collect();
+        packed = pack_cuts(&cuts);
+    }
+
+    Ok((cuts, packed))
+}
+
+fn pack_cuts(cuts: &[Cut]) -> Vec<u8> {
+    let elements: Vec<Element> = cuts
+        .iter()
+        .map(|(range, key)| {
+            let key = match key {
+                Some(key) => Element::Bytes(key.clone().into()),
+                None => Element::Nil,
+            };
+            Element::Tuple(vec![Element::Int(*range as i64), key])
+        })
+        .collect();
+    pack(&elements)
+}
+
+pub fn unpack_cuts(packed: &[u8]) -> Vec<Cut> {
+    let elements: Vec<Element> = unpack(packed).unwrap_or_report();
+    elements
+        .iter()
+        .map(|element| match element {
+            Element::Tuple(cut) => match cut.as_slice() {
+                [Element::Int(range), Element::Nil] => (*range as usize, None),
+                [Element::Int(range), Element::Bytes(key)] => (*range as usize, Some(key.to_vec())),
+                _ => error!("invalid parallel chunk cut {:?}", cut),
+            },
+            _ => error!("invalid parallel chunk cut {:?}", element),
+        })
+        .collect()
+}
+
+// The ranges to read for the chunk between two cuts
+pub fn chunk_ranges(
+    ranges: &[RangeOption<'static>],
+    from: &Cut,
+    to: &Cut,
+) -> Vec<RangeOption<'static>> {
+    let end = match to.1 {
+        Some(_) => to.0 + 1,
+        None => to.0,
+    };
+
+    let mut chunk = ranges[from.0..end].to_vec();
+    if let Some(key) = &from.1 {
+        chunk.first_mut().unwrap().begin = KeySelector::first_greater_or_equal(key.clone());
+    }
+    if let Some(key) = &to.1 {
+        chunk.last_mut().unwrap().end = KeySelector::first_greater_or_equal(key.clone());
+    }
+    chunk
+}
+
 fn commit_transaction() {
+    #[allow(static_mut_refs)]
+    unsafe {
+        WRITTEN_TABLES.clear();
+        READ_VERSION = None;
+        ONLINE_BUILDS.clear();
+        HAS_REBUILT = false;
+    };
+
     #[allow(static_mut_refs)]
     if let Some(txn) = unsafe { TRANSACTION.take() } {
         let result = txn.commit().block_on().unwrap();
@@ -51,10 +313,63 @@ fn commit_transaction() {
     }
 }
 
+// Clears the keys and build states of the storage replaced by rebuilding indexes, in the session transaction so
+// that they're removed together with the commit of the new storage. Postgres deletes the replaced storage once the
+// transaction commits, and we find it the same way. This also covers other storage deleted on commit, like that of
+// dropped indexes, which is fine as none of it is in use anymore.
+fn clear_replaced_indexes() {
+    unsafe {
+        if !HAS_REBUILT {
+            return;
+        }
+
+        let mut locators: *mut RelFileLocator = std::ptr::null_mut();
+        let count = pg_sys::smgrGetPendingDeletes(true, &mut locators);
+        if count == 0 {
+            return;
+        }
+
+        let txn = get_transaction();
+        for locator in std::slice::from_raw_parts(locators, count as usize) {
+            txn.clear_subspace_range(&crate::subspace::index(locator.relNumber));
+            txn.clear(crate::subspace::index_state(locator.relNumber).bytes());
+        }
+        pg_sys::pfree(locators.cast());
+    }
+}
+
 fn abort_transaction() {
     #[allow(static_mut_refs)]
-    unsafe {
-        TRANSACTION.take()
+    let online_builds = unsafe {
+        TRANSACTION.take();
+        WRITTEN_TABLES.clear();
+        READ_VERSION = None;
+        HAS_REBUILT = false;
+        std::mem::take(&mut ONLINE_BUILDS)
     };
+
+    if !online_builds.is_empty() {
+        clear_online_builds(&online_builds);
+    }
     log!("TXN: Transaction aborted");
 }
+
+// Clears the keys and build states left behind by online builds into index storage which no longer exists as the
+// transaction that created it aborted. Raising an error while Postgres aborts a transaction isn't possible, so failures are
+// reported as warnings.
+fn clear_online_builds(relfilenumbers: &[RelFileNumber]) {
+    let result = try_run_in_separate_transaction(|txn| {
+        for relfilenumber in relfilenumbers {
+            txn.clear_subspace_range(&crate::subspace::index(*relfilenumber));
+            txn.clear(crate::subspace::index_state(*relfilenumber).bytes());
+        }
+        Ok(())
+    });
+
+    if let Err(err) = result {
+        warning!(
+            "failed to clear index builds of aborted transaction: {}",
+            err
+        );
+    }
+}